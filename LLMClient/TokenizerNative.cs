@@ -23,6 +23,9 @@ public static class TokenizerNative
     [DllImport(DllName, CallingConvention = CallingConvention.Cdecl)]
     public static extern IntPtr tokenizer_decode(int[] ids, UIntPtr len);
 
+    [DllImport(DllName, CallingConvention = CallingConvention.Cdecl)]
+    public static extern void tokenizer_free_string(IntPtr s);
+
     [DllImport(DllName, CallingConvention = CallingConvention.Cdecl)]
     public static extern void tokenizer_cleanup();
 
@@ -62,9 +65,14 @@ public static class TokenizerNative
             {
                 var ptr = tokenizer_decode(ids, (UIntPtr)len);
                 if (ptr == IntPtr.Zero) return null;
-                var str = Marshal.PtrToStringAnsi(ptr);
-                // Marshal.FreeHGlobal(ptr); // Rust zarządza pamięcią
-                return str;
+                try
+                {
+                    return Marshal.PtrToStringUTF8(ptr);
+                }
+                finally
+                {
+                    tokenizer_free_string(ptr); // pamięć zaalokowana po stronie Rusta
+                }
             }
             catch (Exception ex)
             {
@@ -0,0 +1,101 @@
+use crate::encoding;
+use crate::error::{Error, ErrorCode};
+use tokenizers::utils::padding::{pad_encodings, PaddingDirection, PaddingParams, PaddingStrategy};
+use tokenizers::utils::truncation::{TruncationDirection, TruncationParams, TruncationStrategy};
+use tokenizers::{Encoding, Tokenizer};
+
+pub const PADDING_LONGEST: u32 = 0;
+pub const PADDING_FIXED: u32 = 1;
+
+pub const TRUNCATION_NONE: u32 = 0;
+/// Keeps the beginning of the text.
+pub const TRUNCATION_RIGHT: u32 = 1;
+/// Keeps the end of the text.
+pub const TRUNCATION_LEFT: u32 = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct BatchEncodeOptions {
+    pub add_special_tokens: bool,
+    pub padding: u32,
+    /// Sequence length for `PADDING_FIXED`.
+    pub pad_to_length: usize,
+    pub pad_left: bool,
+    pub truncation: u32,
+    /// Maximum length including special tokens.
+    pub max_length: usize,
+}
+
+/// Encodes, truncates and pads `texts` to a common length. Every returned encoding has the same length.
+pub fn encode(
+    tokenizer: &Tokenizer,
+    texts: Vec<&str>,
+    options: &BatchEncodeOptions,
+) -> Result<Vec<Encoding>, Error> {
+    let truncation = match options.truncation {
+        TRUNCATION_NONE => None,
+        TRUNCATION_RIGHT | TRUNCATION_LEFT if options.max_length > 0 => Some(TruncationParams {
+            direction: if options.truncation == TRUNCATION_LEFT {
+                TruncationDirection::Left
+            } else {
+                TruncationDirection::Right
+            },
+            max_length: options.max_length,
+            strategy: TruncationStrategy::LongestFirst,
+            stride: 0,
+        }),
+        _ => {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!(
+                    "invalid truncation {} with max_length {}",
+                    options.truncation, options.max_length
+                ),
+            ))
+        }
+    };
+    let strategy = match options.padding {
+        PADDING_LONGEST => PaddingStrategy::BatchLongest,
+        PADDING_FIXED if options.pad_to_length > 0 => PaddingStrategy::Fixed(options.pad_to_length),
+        _ => {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!(
+                    "invalid padding {} with pad_to_length {}",
+                    options.padding, options.pad_to_length
+                ),
+            ))
+        }
+    };
+
+    let mut encodings = encoding::encode_batch(
+        tokenizer,
+        texts,
+        options.add_special_tokens,
+        truncation.as_ref(),
+    )?;
+    let (pad_id, pad_token) = encoding::pad_token(tokenizer);
+    let padding = PaddingParams {
+        strategy,
+        direction: if options.pad_left {
+            PaddingDirection::Left
+        } else {
+            PaddingDirection::Right
+        },
+        pad_to_multiple_of: None,
+        pad_id,
+        pad_type_id: 0,
+        pad_token,
+    };
+    pad_encodings(&mut encodings, &padding)?;
+    // Fixed padding never shortens a sequence, so re-pad everything to the longest one.
+    let seq_len = encodings.iter().map(|e| e.len()).max().unwrap_or(0);
+    if encodings.iter().any(|e| e.len() != seq_len) {
+        let padding = PaddingParams {
+            strategy: PaddingStrategy::Fixed(seq_len),
+            ..padding
+        };
+        pad_encodings(&mut encodings, &padding)?;
+    }
+    Ok(encodings)
+}
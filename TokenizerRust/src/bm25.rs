@@ -0,0 +1,201 @@
+use crate::error::Error;
+use crate::similarity;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokenizers::Tokenizer;
+
+pub const DEFAULT_K1: f32 = 1.2;
+pub const DEFAULT_B: f32 = 0.75;
+
+/// Turns text into index terms. Both analyzers lowercase and fold Polish diacritics first,
+/// so `Żółw`, `żółw` and `zolw` are the same term.
+pub enum Analyzer {
+    /// Runs of letters, digits and `_`, which keeps identifiers like `max_tokens` whole.
+    Words,
+    /// Token strings of a loaded tokenizer.
+    Tokenizer(Arc<Tokenizer>),
+}
+
+impl Analyzer {
+    pub fn terms(&self, text: &str) -> Result<Vec<String>, Error> {
+        let text = fold(text);
+        match self {
+            Analyzer::Words => Ok(text
+                .split(|c: char| !c.is_alphanumeric() && c != '_')
+                .filter(|word| !word.is_empty())
+                .map(str::to_string)
+                .collect()),
+            Analyzer::Tokenizer(tokenizer) => {
+                let encoding = tokenizer.encode(text, false)?;
+                Ok(encoding.get_tokens().to_vec())
+            }
+        }
+    }
+}
+
+/// Lowercases and strips the Polish diacritics that users often leave out when typing.
+pub fn fold(text: &str) -> String {
+    text.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            'ą' => 'a',
+            'ć' => 'c',
+            'ę' => 'e',
+            'ł' => 'l',
+            'ń' => 'n',
+            'ó' => 'o',
+            'ś' => 's',
+            'ź' | 'ż' => 'z',
+            c => c,
+        })
+        .collect()
+}
+
+/// Inverted index over message texts, keyed by message id, scored with Okapi BM25.
+pub struct Bm25 {
+    analyzer: Analyzer,
+    k1: f32,
+    b: f32,
+    postings: HashMap<String, HashMap<i64, u32>>,
+    documents: HashMap<i64, Document>,
+    total_len: u64,
+}
+
+struct Document {
+    len: u32,
+    terms: Vec<String>,
+}
+
+impl Bm25 {
+    pub fn new(analyzer: Analyzer, k1: f32, b: f32) -> Self {
+        Bm25 {
+            analyzer,
+            k1,
+            b,
+            postings: HashMap::new(),
+            documents: HashMap::new(),
+            total_len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// Indexes a message, replacing its previous text if it was already indexed.
+    pub fn upsert(&mut self, id: i64, text: &str) -> Result<(), Error> {
+        let terms = self.analyzer.terms(text)?;
+        self.remove(id);
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for term in &terms {
+            *counts.entry(term.clone()).or_default() += 1;
+        }
+        let unique = counts.keys().cloned().collect();
+        for (term, count) in counts {
+            self.postings.entry(term).or_default().insert(id, count);
+        }
+        self.total_len += terms.len() as u64;
+        self.documents.insert(
+            id,
+            Document {
+                len: terms.len() as u32,
+                terms: unique,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: i64) -> bool {
+        let Some(document) = self.documents.remove(&id) else {
+            return false;
+        };
+        for term in document.terms {
+            if let Some(posting) = self.postings.get_mut(&term) {
+                posting.remove(&id);
+                if posting.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+        self.total_len -= u64::from(document.len);
+        true
+    }
+
+    /// Returns up to `k` messages matching any query term as `(id, score)`, best first;
+    /// ties go to the lower id.
+    pub fn search(&self, query: &str, k: usize) -> Result<Vec<(i64, f32)>, Error> {
+        let mut terms = self.analyzer.terms(query)?;
+        terms.sort();
+        terms.dedup();
+        if self.documents.is_empty() {
+            return Ok(Vec::new());
+        }
+        let count = self.documents.len() as f32;
+        let average_len = self.total_len as f32 / count;
+        let mut scores: HashMap<i64, f32> = HashMap::new();
+        for posting in terms.iter().filter_map(|term| self.postings.get(term)) {
+            let matches = posting.len() as f32;
+            let idf = (1.0 + (count - matches + 0.5) / (matches + 0.5)).ln();
+            for (&id, &tf) in posting {
+                let len = self.documents[&id].len as f32;
+                let tf = tf as f32;
+                let norm = self.k1 * (1.0 - self.b + self.b * len / average_len.max(1.0));
+                *scores.entry(id).or_default() += idf * tf * (self.k1 + 1.0) / (tf + norm);
+            }
+        }
+        let mut scores: Vec<(i64, f32)> = scores.into_iter().collect();
+        scores.sort_unstable_by_key(|&(id, _)| id);
+        let best = similarity::best_k(scores.iter().map(|s| s.1).enumerate(), k, false);
+        Ok(best
+            .into_iter()
+            .map(|(i, score)| (scores[i].0, score))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> Bm25 {
+        let mut index = Bm25::new(Analyzer::Words, DEFAULT_K1, DEFAULT_B);
+        let messages = [
+            "Ustaw max_tokens na 512 w konfiguracji.",
+            "Żółw to gad, a nie płaz.",
+            "Konfiguracja modelu jest w pliku config.json, a konfiguracja UI osobno.",
+            "Konfiguracja jest gotowa.",
+        ];
+        for (id, text) in messages.iter().enumerate() {
+            index.upsert(id as i64 + 1, text).unwrap();
+        }
+        index
+    }
+
+    #[test]
+    fn folds_case_and_polish_diacritics() {
+        assert_eq!(
+            Analyzer::Words
+                .terms("Zażółć GĘŚLĄ-jaźń max_tokens")
+                .unwrap(),
+            ["zazolc", "gesla", "jazn", "max_tokens"]
+        );
+        let index = index();
+        assert_eq!(index.search("zolw", 5).unwrap()[0].0, 2);
+        assert_eq!(index.search("ŻÓŁW", 5).unwrap()[0].0, 2);
+    }
+
+    #[test]
+    fn ranks_rare_terms_and_short_messages_higher() {
+        let mut index = index();
+        let found = index.search("max_tokens konfiguracja", 5).unwrap();
+        assert_eq!(found.iter().map(|f| f.0).collect::<Vec<_>>(), [1, 4, 3]);
+        assert!(index.search("nieistniejące", 5).unwrap().is_empty());
+
+        index.upsert(1, "inny tekst").unwrap();
+        assert_eq!(index.search("max_tokens", 5).unwrap(), []);
+        assert!(index.remove(3));
+        assert!(!index.remove(3));
+        assert_eq!(index.search("konfiguracja", 5).unwrap()[0].0, 4);
+        assert_eq!(index.len(), 3);
+    }
+}
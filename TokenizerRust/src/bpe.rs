@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+/// Derives BPE merges from a ranked vocabulary, as used by tiktoken and SentencePiece BPE models:
+/// the adjacent pair whose concatenation has the lowest rank is merged first.
+///
+/// Each token gets exactly one merge: the last step of running BPE on the token itself with only
+/// the lower-ranked merges, the way `transformers` converts tiktoken files.
+pub fn merges_by_rank(vocab: &HashMap<String, u32>) -> Vec<(String, String)> {
+    let mut merges: Vec<_> = vocab
+        .iter()
+        .filter_map(|(token, &rank)| match bpe(vocab, token, rank)[..] {
+            [left, right] => Some((rank, left, right)),
+            _ => None,
+        })
+        .collect();
+    merges.sort_unstable();
+    merges
+        .into_iter()
+        .map(|(_, left, right)| (left.to_string(), right.to_string()))
+        .collect()
+}
+
+/// Splits `token` into characters and merges the lowest-ranked adjacent pair, leftmost first,
+/// until no pair ranked below `max_rank` is left.
+fn bpe<'a>(vocab: &HashMap<String, u32>, token: &'a str, max_rank: u32) -> Vec<&'a str> {
+    let mut bounds: Vec<usize> = token
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([token.len()])
+        .collect();
+    while let Some((rank, i)) = (0..bounds.len().saturating_sub(2))
+        .filter_map(|i| Some((*vocab.get(&token[bounds[i]..bounds[i + 2]])?, i)))
+        .min()
+    {
+        if rank >= max_rank {
+            break;
+        }
+        bounds.remove(i + 1);
+    }
+    bounds.windows(2).map(|w| &token[w[0]..w[1]]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_one_merge_per_token() {
+        let vocab: HashMap<String, u32> = ["a", "b", "c", "ab", "bc", "abc"]
+            .iter()
+            .enumerate()
+            .map(|(rank, token)| (token.to_string(), rank as u32))
+            .collect();
+        let merges = merges_by_rank(&vocab);
+        let pair = |l: &str, r: &str| (l.to_string(), r.to_string());
+        assert_eq!(merges, [pair("a", "b"), pair("b", "c"), pair("ab", "c")]);
+    }
+}
@@ -0,0 +1,387 @@
+use crate::error::Error;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+
+pub const DEFAULT_MAX_BYTES: usize = 64 << 20;
+
+const MAGIC: &[u8; 4] = b"EMBC";
+const VERSION: u32 = 1;
+const HEADER_LEN: u64 = 8;
+const RECORD_HEADER_LEN: usize = 20;
+// Larger dimensions can only come from a corrupt record.
+const MAX_DIM: usize = 1 << 16;
+// Bookkeeping per memory entry besides the vector: map, recency index and key.
+const ENTRY_OVERHEAD: usize = 96;
+
+/// Cache key: a stable 128-bit FNV-1a hash of the model profile and the normalized text,
+/// so that keys written to disk stay valid across builds.
+pub fn key(profile: &str, text: &str) -> u128 {
+    const OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+    let text = normalize(text);
+    [profile.as_bytes(), &[0xff], text.as_bytes()]
+        .concat()
+        .iter()
+        .fold(OFFSET, |hash, &byte| {
+            (hash ^ byte as u128).wrapping_mul(PRIME)
+        })
+}
+
+/// Texts that embed the same way share an entry: whitespace runs collapse and the ends are trimmed.
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    /// Hits served from the disk file, which are promoted to memory; not included in `hits`.
+    pub disk_hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+/// Embedding cache with a byte-bounded LRU in memory and an optional append-only disk file
+/// that survives restarts.
+pub struct EmbeddingCache {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    max_bytes: usize,
+    entries: HashMap<u128, Entry>,
+    /// Entries by last use, oldest first.
+    recency: BTreeMap<u64, u128>,
+    tick: u64,
+    disk: Option<Disk>,
+    stats: CacheStats,
+}
+
+struct Entry {
+    vector: Arc<[f32]>,
+    tick: u64,
+}
+
+struct Disk {
+    file: File,
+    /// Offset of each record's vector and its length.
+    records: HashMap<u128, (u64, usize)>,
+}
+
+impl EmbeddingCache {
+    pub fn new(max_bytes: usize) -> Self {
+        EmbeddingCache {
+            inner: Mutex::new(Inner {
+                max_bytes,
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+                disk: None,
+                stats: CacheStats::default(),
+            }),
+        }
+    }
+
+    /// Opens or creates the disk file at `path`. A final record cut short by a crash is dropped;
+    /// any other damage fails the open and leaves the file untouched.
+    pub fn with_file(max_bytes: usize, path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let disk = Disk::open(path)
+            .map_err(|e| Error::io(e, format_args!("cannot open {}", path.display())))?;
+        let cache = Self::new(max_bytes);
+        cache.lock().disk = Some(disk);
+        Ok(cache)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Looks up a `dim`-sized vector; an entry of another length counts as a miss.
+    pub fn get(&self, key: u128, dim: usize) -> Option<Arc<[f32]>> {
+        let mut inner = self.lock();
+        if let Some(vector) = inner.touch(key).filter(|v| v.len() == dim) {
+            inner.stats.hits += 1;
+            return Some(vector);
+        }
+        let from_disk = inner
+            .disk
+            .as_mut()
+            .and_then(|disk| disk.read(key, dim).ok().flatten());
+        match from_disk {
+            Some(vector) => {
+                inner.stats.disk_hits += 1;
+                let vector: Arc<[f32]> = vector.into();
+                inner.insert_memory(key, vector.clone());
+                Some(vector)
+            }
+            None => {
+                inner.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&self, key: u128, vector: &[f32]) -> Result<(), Error> {
+        let mut inner = self.lock();
+        if let Some(disk) = inner.disk.as_mut() {
+            disk.append(key, vector)
+                .map_err(|e| Error::io(e, "cannot write the embedding cache"))?;
+        }
+        inner.insert_memory(key, vector.into());
+        Ok(())
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.lock().stats
+    }
+
+    /// Empties the memory tier and resets the counters; the disk file is kept.
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.entries.clear();
+        inner.recency.clear();
+        inner.stats = CacheStats::default();
+    }
+}
+
+impl Inner {
+    fn touch(&mut self, key: u128) -> Option<Arc<[f32]>> {
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.entries.get_mut(&key)?;
+        self.recency.remove(&entry.tick);
+        self.recency.insert(tick, key);
+        entry.tick = tick;
+        Some(entry.vector.clone())
+    }
+
+    fn insert_memory(&mut self, key: u128, vector: Arc<[f32]>) {
+        let bytes = cost(&vector);
+        if bytes > self.max_bytes {
+            return;
+        }
+        self.tick += 1;
+        if let Some(old) = self.entries.insert(
+            key,
+            Entry {
+                vector,
+                tick: self.tick,
+            },
+        ) {
+            self.recency.remove(&old.tick);
+            self.stats.bytes -= cost(&old.vector);
+        }
+        self.recency.insert(self.tick, key);
+        self.stats.bytes += bytes;
+        while self.stats.bytes > self.max_bytes {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                self.stats.bytes -= cost(&evicted.vector);
+            }
+        }
+        self.stats.entries = self.entries.len();
+    }
+}
+
+fn cost(vector: &[f32]) -> usize {
+    std::mem::size_of_val(vector) + ENTRY_OVERHEAD
+}
+
+impl Disk {
+    /// Indexes the records by reading their headers only and seeking past the vectors.
+    fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let len = file.metadata()?.len();
+        if len == 0 {
+            file.write_all(MAGIC)?;
+            file.write_all(&VERSION.to_le_bytes())?;
+            return Ok(Disk {
+                file,
+                records: HashMap::new(),
+            });
+        }
+        let mut header = [0u8; HEADER_LEN as usize];
+        if len < HEADER_LEN
+            || file.read_exact(&mut header).is_err()
+            || &header[..4] != MAGIC
+            || header[4..] != VERSION.to_le_bytes()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not an embedding cache file",
+            ));
+        }
+        let mut records = HashMap::new();
+        let mut offset = HEADER_LEN;
+        let mut reader = BufReader::new(&mut file);
+        let mut record = [0u8; RECORD_HEADER_LEN];
+        while len - offset >= RECORD_HEADER_LEN as u64 {
+            reader.read_exact(&mut record)?;
+            let key = u128::from_le_bytes(record[..16].try_into().unwrap());
+            let dim = u32::from_le_bytes(record[16..].try_into().unwrap()) as usize;
+            let end = record_len(dim).and_then(|len| offset.checked_add(len as u64));
+            match end {
+                Some(end) if end <= len => {
+                    records.insert(key, (offset + RECORD_HEADER_LEN as u64, dim));
+                    reader.seek_relative((dim * 4) as i64)?;
+                    offset = end;
+                }
+                // A plausible record running past the end of the file is a torn append.
+                Some(_) => break,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("corrupt record with dimension {dim} at offset {offset}"),
+                    ))
+                }
+            }
+        }
+        drop(reader);
+        if offset < len {
+            file.set_len(offset)?;
+        }
+        Ok(Disk { file, records })
+    }
+
+    fn read(&mut self, key: u128, dim: usize) -> io::Result<Option<Vec<f32>>> {
+        let Some(&(offset, _)) = self.records.get(&key).filter(|r| r.1 == dim) else {
+            return Ok(None);
+        };
+        let mut bytes = vec![0u8; dim * 4];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut bytes)?;
+        Ok(Some(
+            bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+                .collect(),
+        ))
+    }
+
+    fn append(&mut self, key: u128, vector: &[f32]) -> io::Result<()> {
+        if self.records.get(&key).is_some_and(|r| r.1 == vector.len()) {
+            return Ok(());
+        }
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + vector.len() * 4);
+        record.extend_from_slice(&key.to_le_bytes());
+        record.extend_from_slice(&(vector.len() as u32).to_le_bytes());
+        for x in vector {
+            record.extend_from_slice(&x.to_le_bytes());
+        }
+        let start = self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&record)?;
+        self.records
+            .insert(key, (start + RECORD_HEADER_LEN as u64, vector.len()));
+        Ok(())
+    }
+}
+
+/// Size of a record holding `dim` floats, or `None` for a dimension no embedding has.
+fn record_len(dim: usize) -> Option<usize> {
+    if dim > MAX_DIM {
+        return None;
+    }
+    dim.checked_mul(4)?.checked_add(RECORD_HEADER_LEN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+
+    #[test]
+    fn keys_ignore_whitespace_but_not_profile() {
+        assert_eq!(key("e5/1", "  ala  ma\nkota "), key("e5/1", "ala ma kota"));
+        assert_ne!(key("e5/1", "ala ma kota"), key("e5/2", "ala ma kota"));
+        assert_ne!(key("e5/1", "ala"), key("e5/1", "Ala"));
+    }
+
+    #[test]
+    fn evicts_least_recently_used_by_bytes() {
+        let entry = cost(&[0.0; 4]);
+        let cache = EmbeddingCache::new(2 * entry);
+        cache.insert(1, &[1.0; 4]).unwrap();
+        cache.insert(2, &[2.0; 4]).unwrap();
+        assert!(cache.get(1, 4).is_some());
+        cache.insert(3, &[3.0; 4]).unwrap();
+        assert!(cache.get(2, 4).is_none());
+        assert_eq!(cache.get(1, 4).unwrap()[0], 1.0);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                disk_hits: 0,
+                misses: 1,
+                entries: 2,
+                bytes: 2 * entry,
+            }
+        );
+    }
+
+    #[test]
+    fn disk_tier_survives_reopening_and_torn_writes() {
+        let path = std::env::temp_dir().join(format!("embedding-cache-{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        {
+            let cache = EmbeddingCache::with_file(DEFAULT_MAX_BYTES, &path).unwrap();
+            cache.insert(7, &[0.5, -0.5]).unwrap();
+            cache.insert(8, &[1.0, 2.0, 3.0]).unwrap();
+        }
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&9u128.to_le_bytes()).unwrap();
+        drop(file);
+
+        let cache = EmbeddingCache::with_file(DEFAULT_MAX_BYTES, &path).unwrap();
+        assert_eq!(&*cache.get(8, 3).unwrap(), [1.0, 2.0, 3.0]);
+        assert_eq!(&*cache.get(8, 3).unwrap(), [1.0, 2.0, 3.0]);
+        assert!(cache.get(9, 1).is_none());
+        cache.insert(9, &[9.0]).unwrap();
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.disk_hits, stats.misses), (1, 1, 1));
+        drop(cache);
+
+        let cache = EmbeddingCache::with_file(DEFAULT_MAX_BYTES, &path).unwrap();
+        assert_eq!(&*cache.get(9, 1).unwrap(), [9.0]);
+        assert_eq!(&*cache.get(7, 2).unwrap(), [0.5, -0.5]);
+        assert!(cache.get(7, 3).is_none());
+        assert_eq!(cache.stats().misses, 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_record_is_not_truncated_away() {
+        let path =
+            std::env::temp_dir().join(format!("embedding-corrupt-{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        {
+            let cache = EmbeddingCache::with_file(DEFAULT_MAX_BYTES, &path).unwrap();
+            cache.insert(1, &[1.0]).unwrap();
+            cache.insert(2, &[2.0]).unwrap();
+        }
+        let mut bytes = std::fs::read(&path).unwrap();
+        let dim_at = HEADER_LEN as usize + 16;
+        bytes[dim_at..dim_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+
+        let err = EmbeddingCache::with_file(DEFAULT_MAX_BYTES, &path)
+            .err()
+            .unwrap();
+        assert_eq!(err.code, ErrorCode::Io);
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        let missing = EmbeddingCache::with_file(DEFAULT_MAX_BYTES, "/definitely/missing/cache.bin");
+        assert_eq!(missing.err().unwrap().code, ErrorCode::FileNotFound);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
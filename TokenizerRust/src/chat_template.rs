@@ -0,0 +1,257 @@
+use crate::error::{Error, ErrorCode};
+use minijinja::value::{from_args, Rest, ValueKind};
+use minijinja::{Environment, ErrorKind, State, Value};
+use serde_json::json;
+use std::path::Path;
+
+const TEMPLATE_NAME: &str = "chat";
+const SPECIAL_TOKEN_KEYS: [&str; 4] = ["bos_token", "eos_token", "unk_token", "pad_token"];
+
+/// Jinja `chat_template` from a model's `tokenizer_config.json`, rendered the way
+/// `transformers`' `apply_chat_template` does.
+pub struct ChatTemplate {
+    env: Environment<'static>,
+    special_tokens: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ChatTemplate {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)
+            .map_err(|e| Error::io(e, format_args!("cannot read {}", path.display())))?;
+        let config = serde_json::from_slice(&bytes).map_err(|e| {
+            Error::new(
+                ErrorCode::JsonParse,
+                format!("cannot parse {}: {e}", path.display()),
+            )
+        })?;
+        Self::from_config(&config)
+    }
+
+    pub fn from_config(config: &serde_json::Value) -> Result<Self, Error> {
+        let source = match &config["chat_template"] {
+            serde_json::Value::String(source) => source.clone(),
+            // Newer configs may list several named templates.
+            serde_json::Value::Array(templates) => templates
+                .iter()
+                .find(|t| t["name"] == "default")
+                .or_else(|| templates.first())
+                .and_then(|t| t["template"].as_str())
+                .map(str::to_string)
+                .ok_or_else(|| {
+                    Error::new(ErrorCode::JsonParse, "chat_template list has no template")
+                })?,
+            _ => {
+                return Err(Error::new(
+                    ErrorCode::JsonParse,
+                    "tokenizer_config.json has no chat_template",
+                ))
+            }
+        };
+        let special_tokens = SPECIAL_TOKEN_KEYS
+            .iter()
+            .filter_map(|&key| {
+                let token = &config[key];
+                token
+                    .as_str()
+                    .or_else(|| token["content"].as_str())
+                    .map(|content| (key.to_string(), json!(content)))
+            })
+            .collect();
+
+        let mut env = Environment::new();
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.add_function("raise_exception", raise_exception);
+        env.set_unknown_method_callback(python_method);
+        env.add_template_owned(TEMPLATE_NAME, source)
+            .map_err(template_error)?;
+        Ok(ChatTemplate {
+            env,
+            special_tokens,
+        })
+    }
+
+    /// Renders `messages` (a JSON array of `{"role", "content"}` objects) into a prompt.
+    pub fn render(
+        &self,
+        messages: &serde_json::Value,
+        add_generation_prompt: bool,
+    ) -> Result<String, Error> {
+        if !messages.is_array() {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                "messages must be a JSON array",
+            ));
+        }
+        let mut context = self.special_tokens.clone();
+        context.insert("messages".to_string(), messages.clone());
+        context.insert(
+            "add_generation_prompt".to_string(),
+            json!(add_generation_prompt),
+        );
+        self.env
+            .get_template(TEMPLATE_NAME)
+            .and_then(|template| template.render(&context))
+            .map_err(template_error)
+    }
+}
+
+fn template_error(err: minijinja::Error) -> Error {
+    Error::new(ErrorCode::TemplateError, err.to_string())
+}
+
+fn raise_exception(message: String) -> Result<Value, minijinja::Error> {
+    Err(minijinja::Error::new(ErrorKind::InvalidOperation, message))
+}
+
+/// The Python `str` and `dict` methods that Hugging Face chat templates commonly call.
+fn python_method(
+    _state: &State,
+    value: &Value,
+    method: &str,
+    args: &[Value],
+) -> Result<Value, minijinja::Error> {
+    match (value.kind(), value.as_str()) {
+        (ValueKind::String, Some(s)) => string_method(s, method, args),
+        (ValueKind::Map, _) => map_method(value, method, args),
+        _ => Err(minijinja::Error::from(ErrorKind::UnknownMethod)),
+    }
+}
+
+fn string_method(s: &str, method: &str, args: &[Value]) -> Result<Value, minijinja::Error> {
+    let result = match method {
+        "strip" | "lstrip" | "rstrip" => {
+            let (chars,): (Option<String>,) = from_args(args)?;
+            let trim = |c: char| match &chars {
+                Some(chars) => chars.contains(c),
+                None => c.is_whitespace(),
+            };
+            match method {
+                "strip" => s.trim_matches(trim),
+                "lstrip" => s.trim_start_matches(trim),
+                _ => s.trim_end_matches(trim),
+            }
+            .into()
+        }
+        "startswith" => {
+            let (prefix,): (String,) = from_args(args)?;
+            s.starts_with(&prefix).into()
+        }
+        "endswith" => {
+            let (suffix,): (String,) = from_args(args)?;
+            s.ends_with(&suffix).into()
+        }
+        "upper" => s.to_uppercase().into(),
+        "lower" => s.to_lowercase().into(),
+        "replace" => {
+            let (from, to): (String, String) = from_args(args)?;
+            s.replace(&from, &to).into()
+        }
+        "split" => {
+            let (sep,): (Option<String>,) = from_args(args)?;
+            let parts: Vec<&str> = match &sep {
+                Some(sep) => s.split(sep.as_str()).collect(),
+                None => s.split_whitespace().collect(),
+            };
+            Value::from_serialize(parts)
+        }
+        _ => return Err(minijinja::Error::from(ErrorKind::UnknownMethod)),
+    };
+    Ok(result)
+}
+
+fn map_method(map: &Value, method: &str, args: &[Value]) -> Result<Value, minijinja::Error> {
+    match method {
+        "items" | "keys" | "values" => {
+            let _: () = from_args(args)?;
+            let mut entries = Vec::new();
+            for key in map.try_iter()? {
+                let value = map.get_item(&key)?;
+                entries.push(match method {
+                    "items" => Value::from(vec![key, value]),
+                    "keys" => key,
+                    _ => value,
+                });
+            }
+            Ok(Value::from(entries))
+        }
+        "get" => {
+            let (key, default): (Value, Rest<Value>) = from_args(args)?;
+            let value = map.get_item(&key)?;
+            Ok(if value.is_undefined() {
+                default.first().cloned().unwrap_or(Value::from(()))
+            } else {
+                value
+            })
+        }
+        _ => Err(minijinja::Error::from(ErrorKind::UnknownMethod)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHATML: &str = "{% for message in messages %}{{'<|im_start|>' + message['role'] + '<|im_sep|>' + message['content'] + '<|im_end|>'}}{% endfor %}{% if add_generation_prompt %}{{ '<|im_start|>assistant<|im_sep|>' }}{% endif %}";
+
+    fn messages() -> serde_json::Value {
+        json!([
+            {"role": "system", "content": "Jesteś pomocnym asystentem."},
+            {"role": "user", "content": "  Cześć!  "},
+        ])
+    }
+
+    #[test]
+    fn renders_chatml_with_generation_prompt() {
+        let template = ChatTemplate::from_config(&json!({ "chat_template": CHATML })).unwrap();
+        assert_eq!(
+            template.render(&messages(), true).unwrap(),
+            "<|im_start|>system<|im_sep|>Jesteś pomocnym asystentem.<|im_end|>\
+             <|im_start|>user<|im_sep|>  Cześć!  <|im_end|>\
+             <|im_start|>assistant<|im_sep|>"
+        );
+        assert!(!template
+            .render(&messages(), false)
+            .unwrap()
+            .ends_with("<|im_sep|>"));
+    }
+
+    #[test]
+    fn exposes_special_tokens_and_python_methods() {
+        let config = json!({
+            "bos_token": {"content": "<s>", "lstrip": false},
+            "eos_token": "</s>",
+            "chat_template": [
+                {"name": "tool_use", "template": "unused"},
+                {"name": "default", "template": "{{ bos_token }}{% for m in messages %}{% if m.role.startswith('us') %}[INST] {{ m['content'].strip() }} [/INST]{% endif %}{% endfor %}{{ eos_token }}"}
+            ]
+        });
+        let template = ChatTemplate::from_config(&config).unwrap();
+        assert_eq!(
+            template.render(&messages(), false).unwrap(),
+            "<s>[INST] Cześć! [/INST]</s>"
+        );
+    }
+
+    #[test]
+    fn reports_raise_exception_and_missing_template() {
+        let config = json!({
+            "chat_template": "{% if messages[0]['role'] == 'system' %}{{ raise_exception('System role not supported') }}{% endif %}"
+        });
+        let err = ChatTemplate::from_config(&config)
+            .unwrap()
+            .render(&messages(), true)
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::TemplateError);
+        assert!(err.message.contains("System role not supported"));
+
+        let err = ChatTemplate::from_config(&json!({})).err().unwrap();
+        assert_eq!(err.code, ErrorCode::JsonParse);
+
+        let missing = ChatTemplate::from_file("/definitely/missing/tokenizer_config.json");
+        assert_eq!(missing.err().unwrap().code, ErrorCode::FileNotFound);
+        let directory = ChatTemplate::from_file(env!("CARGO_MANIFEST_DIR"));
+        assert_eq!(directory.err().unwrap().code, ErrorCode::Io);
+    }
+}
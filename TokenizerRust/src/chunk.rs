@@ -0,0 +1,191 @@
+use crate::encoding;
+use crate::error::{Error, ErrorCode};
+use tokenizers::Tokenizer;
+
+// How good a place between two tokens is for ending a chunk; higher is better.
+const WORD: u8 = 1;
+const LINE: u8 = 2;
+const SENTENCE: u8 = 3;
+const PARAGRAPH: u8 = 4;
+/// Before a Markdown heading or code fence, or after a closing fence.
+const SECTION: u8 = 5;
+
+const SENTENCE_ENDS: [char; 4] = ['.', '!', '?', '…'];
+
+/// A chunk of the source text as a byte span, trimmed of surrounding whitespace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Chunk {
+    pub start: usize,
+    pub end: usize,
+    /// Tokens in the chunk, special tokens included when they were requested.
+    pub tokens: usize,
+}
+
+/// Splits `text` into chunks of at most `max_tokens` tokens that overlap by about `overlap`
+/// tokens. Each chunk ends at the best boundary in the second half of its window: a Markdown
+/// section, then a paragraph, sentence, line and word. Code fences are only split between lines.
+pub fn chunk(
+    tokenizer: &Tokenizer,
+    text: &str,
+    add_special_tokens: bool,
+    max_tokens: usize,
+    overlap: usize,
+) -> Result<Vec<Chunk>, Error> {
+    let added = encoding::added_tokens(tokenizer, add_special_tokens);
+    if overlap + added >= max_tokens {
+        return Err(Error::new(
+            ErrorCode::InvalidArgument,
+            format!(
+                "overlap {overlap} plus {added} special tokens leaves no room in chunks of {max_tokens}"
+            ),
+        ));
+    }
+    let budget = max_tokens - added;
+    let encoding = tokenizer.encode(text, false)?;
+    let offsets = encoding.get_offsets();
+    let priorities = token_priorities(text, offsets);
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < offsets.len() {
+        let end = if start + budget >= offsets.len() {
+            offsets.len()
+        } else {
+            // The latest of the best boundaries, so chunks stay close to full.
+            (start + budget / 2 + 1..=start + budget)
+                .max_by_key(|&i| priorities[i])
+                .unwrap()
+        };
+        let (span_start, span_end) = trim(text, offsets[start].0, offsets[end - 1].1);
+        if span_start < span_end {
+            chunks.push(Chunk {
+                start: span_start,
+                end: span_end,
+                tokens: end - start + added,
+            });
+        }
+        if end == offsets.len() {
+            break;
+        }
+        // The earliest of the best boundaries in the overlap, so the next chunk opens cleanly.
+        start = (end.saturating_sub(overlap).max(start + 1)..=end)
+            .rev()
+            .max_by_key(|&i| priorities[i])
+            .unwrap();
+    }
+    Ok(chunks)
+}
+
+/// Priority of ending a chunk before each token; index `0` and `len` are never chosen.
+fn token_priorities(text: &str, offsets: &[(usize, usize)]) -> Vec<u8> {
+    let mut priorities = vec![0; offsets.len() + 1];
+    for (position, priority) in boundaries(text) {
+        // A boundary inside a token moves to the start of that token.
+        let token = offsets.partition_point(|&(_, end)| end <= position);
+        if token > 0 && token < offsets.len() {
+            priorities[token] = priorities[token].max(priority);
+        }
+    }
+    priorities
+}
+
+fn boundaries(text: &str) -> Vec<(usize, u8)> {
+    let mut boundaries = Vec::new();
+    let mut in_fence = false;
+    let mut line_start = 0;
+    for line in text.split_inclusive('\n') {
+        let content = line.trim_start();
+        let line_end = line_start + line.len();
+        if content.starts_with("```") || content.starts_with("~~~") {
+            boundaries.push((if in_fence { line_end } else { line_start }, SECTION));
+            in_fence = !in_fence;
+        } else if in_fence {
+            boundaries.push((line_start, LINE));
+        } else if is_heading(content) {
+            boundaries.push((line_start, SECTION));
+        } else if content.is_empty() {
+            boundaries.push((line_end, PARAGRAPH));
+        } else {
+            boundaries.push((line_start, LINE));
+            let mut chars = line.char_indices().peekable();
+            while let Some((i, c)) = chars.next() {
+                let next_is_space = chars.peek().is_some_and(|&(_, next)| next.is_whitespace());
+                if SENTENCE_ENDS.contains(&c) && next_is_space {
+                    boundaries.push((line_start + i + c.len_utf8(), SENTENCE));
+                } else if c.is_whitespace() {
+                    boundaries.push((line_start + i, WORD));
+                }
+            }
+        }
+        line_start = line_end;
+    }
+    boundaries
+}
+
+fn is_heading(line: &str) -> bool {
+    let level = line.bytes().take_while(|&b| b == b'#').count();
+    (1..=6).contains(&level) && line[level..].starts_with([' ', '\t', '\r', '\n'])
+}
+
+fn trim(text: &str, start: usize, end: usize) -> (usize, usize) {
+    let span = &text[start..end];
+    let start = start + span.len() - span.trim_start().len();
+    (start, start + span.trim().len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokenizer() -> Tokenizer {
+        Tokenizer::from_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/tokenizer.json"
+        ))
+        .unwrap()
+    }
+
+    fn spans<'a>(text: &'a str, chunks: &[Chunk]) -> Vec<&'a str> {
+        chunks.iter().map(|c| &text[c.start..c.end]).collect()
+    }
+
+    #[test]
+    fn prefers_sentence_and_section_boundaries() {
+        let tokenizer = tokenizer();
+        let text = "the quick brown fox jumps. the lazy dog jumps over it. this is a test!";
+        let chunks = chunk(&tokenizer, text, false, 10, 0).unwrap();
+        assert_eq!(
+            spans(text, &chunks),
+            [
+                "the quick brown fox jumps.",
+                "the lazy dog jumps over it.",
+                "this is a test!"
+            ]
+        );
+        assert!(chunks.iter().all(|c| c.tokens <= 10));
+
+        let text = "the quick brown fox jumps over the lazy dog\n# the test\nthis is a long text\n```\nrun a b\n```\nthe quick brown fox jumps over the lazy dog";
+        let chunks = chunk(&tokenizer, text, true, 14, 0).unwrap();
+        assert_eq!(
+            spans(text, &chunks)[1..3],
+            ["# the test\nthis is a long text", "```\nrun a b\n```"]
+        );
+    }
+
+    #[test]
+    fn overlapping_chunks_cover_the_text() {
+        let tokenizer = tokenizer();
+        let text = "the quick brown fox jumps over the lazy dog ".repeat(6);
+        let chunks = chunk(&tokenizer, &text, true, 12, 4).unwrap();
+        assert!(chunks.len() > 5);
+        assert_eq!(chunks[0].start, 0);
+        assert_eq!(chunks.last().unwrap().end, text.trim_end().len());
+        for pair in chunks.windows(2) {
+            assert!(pair[1].start < pair[0].end);
+            assert!(pair[1].start > pair[0].start);
+        }
+        assert!(chunks.iter().all(|c| c.tokens <= 12));
+        assert!(chunk(&tokenizer, &text, true, 6, 4).is_err());
+        assert!(chunk(&tokenizer, "  ", true, 6, 0).unwrap().is_empty());
+    }
+}
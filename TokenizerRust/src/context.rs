@@ -0,0 +1,219 @@
+use crate::chat_template::ChatTemplate;
+use crate::encoding;
+use crate::error::{Error, ErrorCode};
+use serde_json::{json, Value};
+use tokenizers::Tokenizer;
+
+/// Counts prompt tokens exactly: through the chat template when one is given,
+/// otherwise as the sum of the message contents.
+pub struct TokenCounter<'a> {
+    pub tokenizer: &'a Tokenizer,
+    pub template: Option<&'a ChatTemplate>,
+    pub add_generation_prompt: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Packing {
+    /// Index of the oldest history message that fits; everything after it is kept.
+    pub first_kept: usize,
+    /// Bytes of the latest user message content that fit, if it had to be cut.
+    pub truncated_to: Option<usize>,
+    pub token_count: usize,
+}
+
+impl TokenCounter<'_> {
+    pub fn count(&self, messages: &[Value]) -> Result<usize, Error> {
+        match self.template {
+            Some(template) => {
+                let prompt = template
+                    .render(&Value::Array(messages.to_vec()), self.add_generation_prompt)?;
+                Ok(encoding::count(self.tokenizer, &prompt, false)?)
+            }
+            None => messages.iter().try_fold(0, |sum, message| {
+                let content = content(message)?;
+                Ok(sum + encoding::count(self.tokenizer, content, false)?)
+            }),
+        }
+    }
+
+    /// Chooses the history suffix that fits in `budget` tokens next to the system prompt.
+    ///
+    /// Older messages are dropped first. The system prompt and the latest user turn are always
+    /// kept; if they alone exceed the budget, the user message is cut at a token boundary.
+    pub fn pack(
+        &self,
+        system_prompt: Option<&str>,
+        history: &[Value],
+        budget: usize,
+    ) -> Result<Packing, Error> {
+        let system: Vec<Value> = system_prompt
+            .map(|s| json!({"role": "system", "content": s}))
+            .into_iter()
+            .collect();
+        let prompt = |history: &[Value]| [system.as_slice(), history].concat();
+        let Some(latest) = latest_turn(history) else {
+            let token_count = self.count(&system)?;
+            if token_count > budget {
+                return Err(too_small(token_count, budget));
+            }
+            return Ok(Packing {
+                first_kept: 0,
+                truncated_to: None,
+                token_count,
+            });
+        };
+
+        let mandatory = self.count(&prompt(&history[latest..]))?;
+        if mandatory > budget {
+            return self.truncate_latest(&prompt(&history[latest..]), system.len(), budget, latest);
+        }
+        // Fewer messages never need more tokens, so the longest fitting suffix can be bisected.
+        let (mut lo, mut hi, mut token_count) = (0, latest, mandatory);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let count = self.count(&prompt(&history[mid..]))?;
+            if count <= budget {
+                hi = mid;
+                token_count = count;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        Ok(Packing {
+            first_kept: hi,
+            truncated_to: None,
+            token_count,
+        })
+    }
+
+    fn truncate_latest(
+        &self,
+        messages: &[Value],
+        index: usize,
+        budget: usize,
+        first_kept: usize,
+    ) -> Result<Packing, Error> {
+        let mut messages = messages.to_vec();
+        let text = content(&messages[index])?.to_string();
+        let ends: Vec<usize> = encoding::encode(self.tokenizer, &text, false, None)?
+            .get_offsets()
+            .iter()
+            .map(|&(_, end)| end)
+            .collect();
+        let mut count_with = |tokens: usize| {
+            let end = tokens.checked_sub(1).map_or(0, |last| ends[last]);
+            messages[index]["content"] = json!(text[..end]);
+            self.count(&messages).map(|count| (count, end))
+        };
+
+        let (empty, _) = count_with(0)?;
+        if empty > budget {
+            return Err(too_small(empty, budget));
+        }
+        let (mut lo, mut hi, mut best) = (0, ends.len(), (empty, 0));
+        while lo < hi {
+            let mid = (lo + hi).div_ceil(2);
+            let (count, end) = count_with(mid)?;
+            if count <= budget {
+                lo = mid;
+                best = (count, end);
+            } else {
+                hi = mid - 1;
+            }
+        }
+        Ok(Packing {
+            first_kept,
+            truncated_to: Some(best.1),
+            token_count: best.0,
+        })
+    }
+}
+
+/// The latest user message, or the last message if no user message exists.
+pub fn latest_turn(history: &[Value]) -> Option<usize> {
+    history
+        .iter()
+        .rposition(|m| m["role"] == "user")
+        .or(history.len().checked_sub(1))
+}
+
+fn too_small(token_count: usize, budget: usize) -> Error {
+    Error::new(
+        ErrorCode::BufferTooSmall,
+        format!("the system prompt alone needs {token_count} tokens, budget is {budget}"),
+    )
+}
+
+fn content(message: &Value) -> Result<&str, Error> {
+    message["content"].as_str().ok_or_else(|| {
+        Error::new(
+            ErrorCode::InvalidArgument,
+            "message content must be a string",
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/testdata/{name}", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    fn history() -> Vec<Value> {
+        json!([
+            {"role": "user", "content": "Hello"},
+            {"role": "assistant", "content": "Hello world"},
+            {"role": "user", "content": "world Hello world"},
+        ])
+        .as_array()
+        .unwrap()
+        .clone()
+    }
+
+    #[test]
+    fn drops_oldest_messages_first() {
+        let tokenizer = Tokenizer::from_file(fixture("byte_fallback.json")).unwrap();
+        let template = ChatTemplate::from_file(fixture("tokenizer_config.json")).unwrap();
+        let counter = TokenCounter {
+            tokenizer: &tokenizer,
+            template: Some(&template),
+            add_generation_prompt: true,
+        };
+        let history = history();
+        let system = json!({"role": "system", "content": "Hi"});
+        let full = counter
+            .count(&[std::slice::from_ref(&system), history.as_slice()].concat())
+            .unwrap();
+        let packing = counter.pack(Some("Hi"), &history, full).unwrap();
+        assert_eq!(packing.first_kept, 0);
+        assert_eq!(packing.token_count, full);
+
+        let packing = counter.pack(Some("Hi"), &history, full - 1).unwrap();
+        assert_eq!(packing.first_kept, 1);
+        assert!(packing.token_count < full);
+        assert_eq!(packing.truncated_to, None);
+    }
+
+    #[test]
+    fn truncates_latest_user_turn_when_it_does_not_fit() {
+        let tokenizer = Tokenizer::from_file(fixture("byte_fallback.json")).unwrap();
+        let counter = TokenCounter {
+            tokenizer: &tokenizer,
+            template: None,
+            add_generation_prompt: false,
+        };
+        let history = history();
+        let system = counter.count(&[json!({"content": "Hello"})]).unwrap();
+        let packing = counter.pack(Some("Hello"), &history, system + 2).unwrap();
+        assert_eq!(packing.first_kept, 2);
+        assert_eq!(packing.token_count, system + 2);
+        assert_eq!(packing.truncated_to, Some("world Hello".len()));
+
+        let err = counter
+            .pack(Some("Hello"), &history, system - 1)
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::BufferTooSmall);
+    }
+}
@@ -0,0 +1,428 @@
+use crate::batch::{self, BatchEncodeOptions, PADDING_LONGEST, TRUNCATION_RIGHT};
+use crate::cache::{self, EmbeddingCache};
+use crate::error::{Error, ErrorCode};
+use crate::pooling::{self, Pooling};
+use crate::profile::{Profile, TextKind};
+use std::borrow::Cow;
+use std::path::Path;
+use std::sync::Arc;
+use tokenizers::{Encoding, Tokenizer};
+use tract_onnx::prelude::*;
+
+pub const DEFAULT_MAX_LENGTH: usize = 512;
+// Texts run through the model this many at a time, which bounds the padded input tensors.
+const BATCH_SIZE: usize = 32;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EmbedderOptions {
+    /// Maximum tokens per text, special tokens included; `0` for 512.
+    pub max_length: usize,
+    /// One of the `POOLING_*` constants.
+    pub pooling: u32,
+    /// Matryoshka truncation: keeps this many leading dimensions; `0` keeps all of them.
+    pub dimensions: usize,
+    /// L2-normalizes the embeddings, so that dot product equals cosine similarity.
+    pub normalize: bool,
+}
+
+impl Default for EmbedderOptions {
+    fn default() -> Self {
+        EmbedderOptions {
+            max_length: DEFAULT_MAX_LENGTH,
+            pooling: pooling::POOLING_MEAN,
+            dimensions: 0,
+            normalize: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Feed {
+    InputIds,
+    AttentionMask,
+    TokenTypeIds,
+}
+
+/// Sentence embedder: tokenizes, runs an ONNX encoder, pools the last hidden state over the
+/// attention mask, optionally truncates and L2-normalizes it. Models that already output
+/// `[batch, hidden]` sentence embeddings skip pooling.
+pub struct Embedder {
+    tokenizer: Arc<Tokenizer>,
+    model: TypedRunnableModel<TypedModel>,
+    inputs: Vec<Feed>,
+    max_length: usize,
+    pooling: Pooling,
+    normalize: bool,
+    hidden: usize,
+    dim: usize,
+    profile: Option<Profile>,
+    /// Identifies the model and its settings in cache keys: the profile version when there
+    /// is one, otherwise the model path and options.
+    cache_namespace: String,
+}
+
+fn inference_error(context: &str, err: impl std::fmt::Display) -> Error {
+    Error::new(ErrorCode::InferenceFailed, format!("{context}: {err}"))
+}
+
+impl Embedder {
+    pub fn load(
+        path: impl AsRef<Path>,
+        tokenizer: Arc<Tokenizer>,
+        options: &EmbedderOptions,
+    ) -> Result<Self, Error> {
+        let pooling = Pooling::from_raw(options.pooling)?;
+        let path = path.as_ref();
+        if !path.is_file() {
+            return Err(Error::new(
+                ErrorCode::FileNotFound,
+                format!("model not found: {}", path.display()),
+            ));
+        }
+        let context = format!("cannot load {}", path.display());
+        let model = tract_onnx::onnx()
+            .model_for_path(path)
+            .and_then(|model| model.into_optimized())
+            .map_err(|e| inference_error(&context, e))?;
+        let inputs = model
+            .input_outlets()
+            .map_err(|e| inference_error(&context, e))?
+            .iter()
+            .map(|outlet| match model.node(outlet.node).name.as_str() {
+                "input_ids" => Ok(Feed::InputIds),
+                "attention_mask" => Ok(Feed::AttentionMask),
+                "token_type_ids" => Ok(Feed::TokenTypeIds),
+                other => Err(Error::new(
+                    ErrorCode::InvalidArgument,
+                    format!("{context}: unsupported model input {other}"),
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if !inputs.contains(&Feed::InputIds) {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("{context}: the model has no input_ids input"),
+            ));
+        }
+        let hidden = model
+            .output_fact(0)
+            .ok()
+            .and_then(|fact| usize::try_from(fact.shape.last()?.to_i64().ok()?).ok())
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorCode::InvalidArgument,
+                    format!("{context}: the output has no fixed hidden size"),
+                )
+            })?;
+        if options.dimensions > hidden {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!(
+                    "cannot truncate {hidden}-dimensional embeddings to {}",
+                    options.dimensions
+                ),
+            ));
+        }
+        let model = model
+            .into_runnable()
+            .map_err(|e| inference_error(&context, e))?;
+        Ok(Embedder {
+            tokenizer,
+            model,
+            inputs,
+            max_length: match options.max_length {
+                0 => DEFAULT_MAX_LENGTH,
+                max_length => max_length,
+            },
+            pooling,
+            normalize: options.normalize,
+            hidden,
+            dim: match options.dimensions {
+                0 => hidden,
+                dimensions => dimensions,
+            },
+            profile: None,
+            cache_namespace: format!("{}|{options:?}", path.display()),
+        })
+    }
+
+    /// Loads a model configured by `profile`, whose query and document templates `embed_as`
+    /// then applies.
+    pub fn with_profile(
+        path: impl AsRef<Path>,
+        tokenizer: Arc<Tokenizer>,
+        profile: Profile,
+    ) -> Result<Self, Error> {
+        let mut embedder = Self::load(path, tokenizer, &profile.options)?;
+        embedder.cache_namespace = profile.version.clone();
+        embedder.profile = Some(profile);
+        Ok(embedder)
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn prepare<'a>(&self, kind: TextKind, texts: &[&'a str]) -> Vec<Cow<'a, str>> {
+        texts
+            .iter()
+            .map(|&text| match &self.profile {
+                Some(profile) => profile.prepare(kind, text),
+                None => Cow::Borrowed(text),
+            })
+            .collect()
+    }
+
+    /// Embeds `texts` as queries or documents, applying the profile's instruction templates.
+    pub fn embed_as(&self, kind: TextKind, texts: &[&str]) -> Result<Vec<f32>, Error> {
+        let prepared = self.prepare(kind, texts);
+        self.embed(&prepared.iter().map(|t| t.as_ref()).collect::<Vec<_>>())
+    }
+
+    /// Like `embed_as`, taking vectors from `cache` when it has them, so that only the misses
+    /// are tokenized and run through the model, and storing the new ones.
+    pub fn embed_cached(
+        &self,
+        kind: TextKind,
+        texts: &[&str],
+        cache: &EmbeddingCache,
+    ) -> Result<Vec<f32>, Error> {
+        let prepared = self.prepare(kind, texts);
+        let keys: Vec<u128> = prepared
+            .iter()
+            .map(|text| cache::key(&self.cache_namespace, text))
+            .collect();
+        let mut vectors = vec![0f32; texts.len() * self.dim];
+        let mut missing = Vec::new();
+        for ((i, key), out) in keys
+            .iter()
+            .enumerate()
+            .zip(vectors.chunks_exact_mut(self.dim))
+        {
+            match cache.get(*key, self.dim) {
+                Some(vector) => out.copy_from_slice(&vector),
+                None => missing.push(i),
+            }
+        }
+        if missing.is_empty() {
+            return Ok(vectors);
+        }
+        let embedded = self.embed(
+            &missing
+                .iter()
+                .map(|&i| prepared[i].as_ref())
+                .collect::<Vec<_>>(),
+        )?;
+        for (&i, vector) in missing.iter().zip(embedded.chunks_exact(self.dim)) {
+            vectors[i * self.dim..(i + 1) * self.dim].copy_from_slice(vector);
+            cache.insert(keys[i], vector)?;
+        }
+        Ok(vectors)
+    }
+
+    /// Embeds `texts` into a row-major `[texts.len(), dim]` buffer.
+    pub fn embed(&self, texts: &[&str]) -> Result<Vec<f32>, Error> {
+        let mut vectors = Vec::with_capacity(texts.len() * self.dim);
+        for batch in texts.chunks(BATCH_SIZE) {
+            vectors.extend(self.embed_batch(batch)?);
+        }
+        Ok(vectors)
+    }
+
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<f32>, Error> {
+        let options = BatchEncodeOptions {
+            add_special_tokens: true,
+            padding: PADDING_LONGEST,
+            pad_to_length: 0,
+            pad_left: false,
+            truncation: TRUNCATION_RIGHT,
+            max_length: self.max_length,
+        };
+        let encodings = batch::encode(&self.tokenizer, texts.to_vec(), &options)?;
+        let seq = encodings.first().map_or(0, Encoding::len);
+        let column = |values: fn(&Encoding) -> &[u32]| -> Vec<i64> {
+            encodings
+                .iter()
+                .flat_map(|e| values(e).iter().map(|&v| v as i64))
+                .collect()
+        };
+        let mask = column(Encoding::get_attention_mask);
+        let tensor = |values: Vec<i64>| -> Result<TValue, Error> {
+            Tensor::from_shape(&[texts.len(), seq], &values)
+                .map(TValue::from)
+                .map_err(|e| inference_error("cannot build input", e))
+        };
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|input| match input {
+                Feed::InputIds => tensor(column(Encoding::get_ids)),
+                Feed::AttentionMask => tensor(mask.clone()),
+                Feed::TokenTypeIds => tensor(column(Encoding::get_type_ids)),
+            })
+            .collect::<Result<TVec<_>, _>>()?;
+
+        let outputs = self
+            .model
+            .run(inputs)
+            .map_err(|e| inference_error("inference failed", e))?;
+        let output = outputs[0]
+            .to_array_view::<f32>()
+            .map_err(|e| inference_error("unexpected output", e))?;
+        let hidden = output
+            .as_slice()
+            .ok_or_else(|| inference_error("unexpected output", "not contiguous"))?;
+        let vectors = match output.shape() {
+            [b, s, d] if *b == texts.len() && *s == seq && *d == self.hidden => {
+                pooling::pool(self.pooling, hidden, &mask, texts.len(), seq, self.hidden)
+            }
+            [b, d] if *b == texts.len() && *d == self.hidden => hidden.to_vec(),
+            shape => {
+                return Err(inference_error(
+                    "unexpected output",
+                    format!("shape {shape:?} for {} texts of {seq} tokens", texts.len()),
+                ))
+            }
+        };
+        let mut vectors = if self.dim < self.hidden {
+            pooling::truncate(&vectors, self.hidden, self.dim)
+        } else {
+            vectors
+        };
+        if self.normalize {
+            pooling::normalize(&mut vectors, self.dim);
+        }
+        Ok(vectors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/testdata/{name}", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    // The test encoder maps token `i` to `[i, 1, -i / 2, i % 3]`.
+    fn row(id: u32) -> [f32; 4] {
+        let i = id as f32;
+        [i, 1.0, -0.5 * i, (id % 3) as f32]
+    }
+
+    #[test]
+    fn embeds_with_masked_mean_pooling() {
+        let tokenizer = Arc::new(Tokenizer::from_file(fixture("tokenizer.json")).unwrap());
+        let options = EmbedderOptions {
+            max_length: 16,
+            ..Default::default()
+        };
+        let embedder =
+            Embedder::load(fixture("encoder.onnx"), tokenizer.clone(), &options).unwrap();
+        assert_eq!(embedder.dim(), 4);
+
+        let texts = ["hello world", "the quick brown fox jumps"];
+        let vectors = embedder.embed(&texts).unwrap();
+        assert_eq!(vectors.len(), 2 * 4);
+        for (text, vector) in texts.iter().zip(vectors.chunks_exact(4)) {
+            let ids = tokenizer.encode(*text, true).unwrap().get_ids().to_vec();
+            let mut expected = [0f32; 4];
+            for id in &ids {
+                for (e, x) in expected.iter_mut().zip(row(*id)) {
+                    *e += x / ids.len() as f32;
+                }
+            }
+            pooling::normalize(&mut expected, 4);
+            for (x, e) in vector.iter().zip(expected) {
+                assert!((x - e).abs() < 1e-5, "{vector:?} != {expected:?}");
+            }
+        }
+        assert!(embedder.embed(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn pools_last_token_and_truncates() {
+        let tokenizer = Arc::new(Tokenizer::from_file(fixture("tokenizer.json")).unwrap());
+        let options = EmbedderOptions {
+            pooling: pooling::POOLING_LAST_TOKEN,
+            dimensions: 2,
+            ..Default::default()
+        };
+        let embedder =
+            Embedder::load(fixture("encoder.onnx"), tokenizer.clone(), &options).unwrap();
+        assert_eq!(embedder.dim(), 2);
+        let vectors = embedder.embed(&["hello", "the lazy dog"]).unwrap();
+        let sep = tokenizer.token_to_id("[SEP]").unwrap();
+        let mut expected = row(sep)[..2].to_vec();
+        pooling::normalize(&mut expected, 2);
+        assert_eq!(vectors, [expected.clone(), expected].concat());
+
+        let options = EmbedderOptions {
+            dimensions: 5,
+            ..Default::default()
+        };
+        let err = Embedder::load(fixture("encoder.onnx"), tokenizer, &options).err();
+        assert_eq!(err.unwrap().code, ErrorCode::InvalidArgument);
+    }
+
+    #[test]
+    fn applies_profile_templates_and_length() {
+        let tokenizer = Arc::new(Tokenizer::from_file(fixture("tokenizer.json")).unwrap());
+        let profile = Profile::next_to(fixture("tokenizer.json")).unwrap();
+        let embedder =
+            Embedder::with_profile(fixture("encoder.onnx"), tokenizer.clone(), profile).unwrap();
+        assert_eq!(embedder.profile().unwrap().version, "tiny-encoder/1");
+        let text = "the quick brown fox jumps over the lazy dog";
+        let query = embedder.embed_as(TextKind::Query, &[text]).unwrap();
+        // CLS pooling sees only the first token, so every text embeds the same way.
+        let cls = tokenizer.token_to_id("[CLS]").unwrap();
+        let mut expected = row(cls).to_vec();
+        pooling::normalize(&mut expected, 4);
+        assert_eq!(query, expected);
+        assert_eq!(
+            embedder.embed_as(TextKind::Query, &["query: a"]).unwrap(),
+            embedder.embed(&["query: a"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn cache_hits_skip_the_model() {
+        let tokenizer = Arc::new(Tokenizer::from_file(fixture("tokenizer.json")).unwrap());
+        let profile = Profile::next_to(fixture("tokenizer.json")).unwrap();
+        let embedder = Embedder::with_profile(fixture("encoder.onnx"), tokenizer, profile).unwrap();
+        let cache = EmbeddingCache::new(cache::DEFAULT_MAX_BYTES);
+        let texts = ["hello world", "the lazy dog"];
+        let first = embedder
+            .embed_cached(TextKind::Document, &texts, &cache)
+            .unwrap();
+        assert_eq!(
+            first,
+            embedder.embed_as(TextKind::Document, &texts).unwrap()
+        );
+        let again = embedder
+            .embed_cached(TextKind::Document, &["the  lazy dog ", "hello"], &cache)
+            .unwrap();
+        assert_eq!(again[..4], first[4..]);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 3, 3));
+        embedder
+            .embed_cached(TextKind::Query, &["hello world"], &cache)
+            .unwrap();
+        assert_eq!(cache.stats().misses, 4);
+    }
+
+    #[test]
+    fn reports_missing_and_invalid_models() {
+        let tokenizer = Arc::new(Tokenizer::from_file(fixture("tokenizer.json")).unwrap());
+        let options = EmbedderOptions::default();
+        let missing = Embedder::load(fixture("missing.onnx"), tokenizer.clone(), &options);
+        assert_eq!(missing.err().unwrap().code, ErrorCode::FileNotFound);
+        let invalid = Embedder::load(fixture("tokenizer.json"), tokenizer, &options);
+        assert_eq!(invalid.err().unwrap().code, ErrorCode::InferenceFailed);
+    }
+}
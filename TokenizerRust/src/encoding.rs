@@ -0,0 +1,169 @@
+use crate::error::{Error, ErrorCode};
+use tokenizers::utils::parallelism::MaybeParallelIterator;
+use tokenizers::utils::truncation::{truncate_encodings, TruncationParams};
+use tokenizers::{Encoding, PostProcessor, Tokenizer};
+
+const PAD_TOKENS: [&str; 3] = ["[PAD]", "<pad>", "<|pad|>"];
+
+/// Removes the truncation and padding configured in `tokenizer.json`. `Tokenizer::encode`
+/// applies them even without special tokens, which would pad before `[SEP]` and cut text
+/// before this module's own truncation runs, so every tokenizer is stored this way.
+pub fn prepare(mut tokenizer: Tokenizer) -> tokenizers::Result<Tokenizer> {
+    tokenizer.with_truncation(None)?.with_padding(None);
+    Ok(tokenizer)
+}
+
+pub fn encode(
+    tokenizer: &Tokenizer,
+    text: &str,
+    add_special_tokens: bool,
+    truncation: Option<&TruncationParams>,
+) -> tokenizers::Result<Encoding> {
+    let encoding = tokenizer.encode(text, false)?;
+    post_process(tokenizer, encoding, add_special_tokens, truncation)
+}
+
+/// Counts tokens without post-processing the encoding; special tokens are added arithmetically.
+pub fn count(
+    tokenizer: &Tokenizer,
+    text: &str,
+    add_special_tokens: bool,
+) -> tokenizers::Result<usize> {
+    Ok(tokenizer.encode(text, false)?.len() + added_tokens(tokenizer, add_special_tokens))
+}
+
+pub fn count_batch(
+    tokenizer: &Tokenizer,
+    texts: Vec<&str>,
+    add_special_tokens: bool,
+) -> tokenizers::Result<Vec<usize>> {
+    let added = added_tokens(tokenizer, add_special_tokens);
+    Ok(tokenizer
+        .encode_batch(texts, false)?
+        .iter()
+        .map(|encoding| encoding.len() + added)
+        .collect())
+}
+
+pub struct Windows {
+    pub encodings: Vec<Encoding>,
+    /// Length of the untruncated encoding, special tokens included.
+    pub full_len: usize,
+}
+
+/// Encodes `text` into overlapping windows of at most `max_len` tokens, in reading order.
+pub fn encode_windows(
+    tokenizer: &Tokenizer,
+    text: &str,
+    add_special_tokens: bool,
+    max_len: usize,
+    stride: usize,
+) -> Result<Windows, Error> {
+    let encoding = tokenizer.encode(text, false)?;
+    let added = added_tokens(tokenizer, add_special_tokens);
+    if stride + added >= max_len {
+        return Err(Error::new(
+            ErrorCode::InvalidArgument,
+            format!("stride {stride} plus {added} special tokens leaves no room in windows of {max_len}"),
+        ));
+    }
+    let full_len = encoding.len() + added;
+    let truncation = TruncationParams {
+        max_length: max_len,
+        stride,
+        ..Default::default()
+    };
+    let mut encoding = post_process(tokenizer, encoding, add_special_tokens, Some(&truncation))?;
+    let overflowing = encoding.take_overflowing();
+    let mut encodings = Vec::with_capacity(overflowing.len() + 1);
+    encodings.push(encoding);
+    encodings.extend(overflowing);
+    Ok(Windows {
+        encodings,
+        full_len,
+    })
+}
+
+/// Encodes `texts` in parallel, truncating the content before special tokens are added,
+/// so that `[CLS]`/`[SEP]` or `<s>`/`</s>` survive the cut.
+pub fn encode_batch(
+    tokenizer: &Tokenizer,
+    texts: Vec<&str>,
+    add_special_tokens: bool,
+    truncation: Option<&TruncationParams>,
+) -> tokenizers::Result<Vec<Encoding>> {
+    tokenizer
+        .encode_batch(texts, false)?
+        .into_maybe_par_iter()
+        .map(|encoding| post_process(tokenizer, encoding, add_special_tokens, truncation))
+        .collect()
+}
+
+fn post_process(
+    tokenizer: &Tokenizer,
+    encoding: Encoding,
+    add_special_tokens: bool,
+    truncation: Option<&TruncationParams>,
+) -> tokenizers::Result<Encoding> {
+    let processor = tokenizer.get_post_processor();
+    let encoding = match truncation {
+        Some(params) => {
+            let added = added_tokens(tokenizer, add_special_tokens);
+            let params = TruncationParams {
+                max_length: params.max_length.saturating_sub(added),
+                ..params.clone()
+            };
+            truncate_encodings(encoding, None, &params)?.0
+        }
+        None => encoding,
+    };
+    match processor {
+        Some(p) => p.process(encoding, None, add_special_tokens),
+        None => Ok(encoding),
+    }
+}
+
+pub fn added_tokens(tokenizer: &Tokenizer, add_special_tokens: bool) -> usize {
+    match tokenizer.get_post_processor() {
+        Some(p) if add_special_tokens => p.added_tokens(false),
+        _ => 0,
+    }
+}
+
+/// Converts byte offsets into `text` to UTF-16 code unit offsets, as used by .NET strings.
+/// Offsets that fall inside a character are widened to the whole character.
+pub fn utf16_offsets(text: &str, offsets: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut boundaries = Vec::with_capacity(text.len() + 1);
+    let mut units = 0;
+    for (byte, ch) in text.char_indices() {
+        boundaries.push((byte, units));
+        units += ch.len_utf16();
+    }
+    boundaries.push((text.len(), units));
+
+    let to_utf16 =
+        |byte: usize, round_up: bool| match boundaries.binary_search_by_key(&byte, |b| b.0) {
+            Ok(i) => boundaries[i].1,
+            Err(i) if round_up => boundaries[i.min(boundaries.len() - 1)].1,
+            Err(i) => boundaries[i - 1].1,
+        };
+    offsets
+        .iter()
+        .map(|&(start, end)| (to_utf16(start, false), to_utf16(end, true)))
+        .collect()
+}
+
+/// Padding id and token declared by the tokenizer, which `prepare` removes, falling back to the usual pad tokens and then to `0`.
+pub fn pad_token(tokenizer: &Tokenizer) -> (u32, String) {
+    if let Some(padding) = tokenizer.get_padding() {
+        return (padding.pad_id, padding.pad_token.clone());
+    }
+    PAD_TOKENS
+        .iter()
+        .find_map(|&token| {
+            tokenizer
+                .token_to_id(token)
+                .map(|id| (id, token.to_string()))
+        })
+        .unwrap_or((0, PAD_TOKENS[0].to_string()))
+}
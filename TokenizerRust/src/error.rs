@@ -0,0 +1,91 @@
+use crate::log::{self, Level};
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::fmt;
+use std::io;
+use std::os::raw::c_char;
+use std::ptr;
+
+/// Status codes returned by the exported functions. Values are stable across releases;
+/// the first three match the codes returned before this enum existed.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    Ok = 0,
+    InvalidUtf8 = -1,
+    NotInitialized = -2,
+    EncodeFailed = -3,
+    BufferTooSmall = -4,
+    InvalidArgument = -5,
+    FileNotFound = -6,
+    JsonParse = -7,
+    DecodeFailed = -8,
+    NullPointer = -9,
+    Panic = -10,
+    TemplateError = -11,
+    InferenceFailed = -12,
+    /// Reading or writing a file failed for a reason other than the file being missing.
+    Io = -13,
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Error {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// `FileNotFound` for a missing file, `Io` for any other read or write failure.
+    pub fn io(err: io::Error, context: impl fmt::Display) -> Self {
+        let code = match err.kind() {
+            io::ErrorKind::NotFound => ErrorCode::FileNotFound,
+            _ => ErrorCode::Io,
+        };
+        Error::new(code, format!("{context}: {err}"))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl From<tokenizers::Error> for Error {
+    fn from(err: tokenizers::Error) -> Self {
+        Error::new(ErrorCode::EncodeFailed, err.to_string())
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<(ErrorCode, Option<CString>)> = const { RefCell::new((ErrorCode::Ok, None)) };
+}
+
+/// Stores `err` as the last error of the calling thread and returns its code.
+pub fn report(err: Error) -> i32 {
+    log::log(Level::Debug, || err.to_string());
+    let message = CString::new(err.message.replace('\0', " ")).ok();
+    LAST_ERROR.with(|last| *last.borrow_mut() = (err.code, message));
+    err.code as i32
+}
+
+pub fn last_error_code() -> ErrorCode {
+    LAST_ERROR.with(|last| last.borrow().0)
+}
+
+/// The pointer stays valid until the next failing call on the same thread.
+pub fn last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .1
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
@@ -1,68 +1,1423 @@
+use crate::batch::{self, BatchEncodeOptions};
+use crate::encoding;
+use crate::error::{self, Error, ErrorCode};
+use crate::log::{self, Level};
+use crate::registry::{self, Handle, INVALID_HANDLE};
+use crate::sentencepiece;
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr;
-use once_cell::sync::OnceCell;
+use std::sync::Arc;
+use tokenizers::utils::truncation::TruncationParams;
 use tokenizers::Tokenizer;
 
-static TOKENIZER: OnceCell<Tokenizer> = OnceCell::new();
+mod bm25;
+mod cache;
+mod chat;
+mod chunk;
+mod context;
+mod embed;
+mod hnsw;
+mod quantize;
+mod similarity;
+mod stop;
+mod stream;
+mod tiktoken;
 
-#[no_mangle]
-pub extern "C" fn tokenizer_init(path: *const c_char) -> i32 {
-    let c_str = unsafe { CStr::from_ptr(path) };
-    let path_str = match c_str.to_str() {
-        Ok(s) => s,
-        Err(_) => return -1,
-    };
-    println!("[tokenizer_rust] tokenizer_init | path={}", path_str);
-    match Tokenizer::from_file(path_str) {
-        Ok(tokenizer) => {
-            let _ = TOKENIZER.set(tokenizer);
-            0
+pub const OFFSETS_BYTES: u32 = 0;
+pub const OFFSETS_UTF16: u32 = 1;
+
+/// Runs the body of an exported function, turning errors and panics into a status code.
+fn status(f: impl FnOnce() -> Result<i32, Error>) -> i32 {
+    guarded(f).unwrap_or_else(error::report)
+}
+
+fn or_null<T>(f: impl FnOnce() -> Result<*mut T, Error>) -> *mut T {
+    guarded(f).unwrap_or_else(|e| {
+        error::report(e);
+        ptr::null_mut()
+    })
+}
+
+/// Unwinding across `extern "C"` aborts the host process, so every export goes through here.
+fn guarded<T>(f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(Error::new(ErrorCode::Panic, message))
+    })
+}
+
+fn null_error(name: &str) -> Error {
+    Error::new(ErrorCode::NullPointer, format!("{name} is null"))
+}
+
+fn str_arg<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, Error> {
+    if ptr.is_null() {
+        return Err(null_error(name));
+    }
+    unsafe { CStr::from_ptr(ptr) }.to_str().map_err(|e| {
+        Error::new(
+            ErrorCode::InvalidUtf8,
+            format!("{name} is not valid UTF-8: {e}"),
+        )
+    })
+}
+
+/// Empty slices may be passed as null.
+fn in_slice<'a, T>(ptr: *const T, len: usize, name: &str) -> Result<&'a [T], Error> {
+    match (ptr.is_null(), len) {
+        (_, 0) => Ok(&[]),
+        (true, _) => Err(null_error(name)),
+        (false, _) => Ok(unsafe { std::slice::from_raw_parts(ptr, len) }),
+    }
+}
+
+fn out_slice<'a, T>(ptr: *mut T, len: usize, name: &str) -> Result<&'a mut [T], Error> {
+    match (ptr.is_null(), len) {
+        (_, 0) => Ok(&mut []),
+        (true, _) => Err(null_error(name)),
+        (false, _) => Ok(unsafe { std::slice::from_raw_parts_mut(ptr, len) }),
+    }
+}
+
+/// For optional output buffers, where null means the caller does not want them.
+fn opt_out_slice<'a, T>(ptr: *mut T, len: usize) -> Option<&'a mut [T]> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(unsafe { std::slice::from_raw_parts_mut(ptr, len) })
+    }
+}
+
+fn out_ref<'a, T>(ptr: *mut T, name: &str) -> Result<&'a mut T, Error> {
+    unsafe { ptr.as_mut() }.ok_or_else(|| null_error(name))
+}
+
+/// Copies `text` into a caller buffer without a terminating NUL. The required size is always
+/// stored, so the caller can retry after `BufferTooSmall`.
+fn write_utf8(
+    text: &str,
+    out_buf: *mut u8,
+    buf_len: usize,
+    out_required: &mut usize,
+) -> Result<i32, Error> {
+    *out_required = text.len();
+    if text.len() > buf_len {
+        return Err(Error::new(
+            ErrorCode::BufferTooSmall,
+            format!("{} bytes do not fit in buffer of {buf_len}", text.len()),
+        ));
+    }
+    out_slice(out_buf, text.len(), "out_buf")?.copy_from_slice(text.as_bytes());
+    Ok(text.len() as i32)
+}
+
+fn check_offset_kind(offset_kind: u32) -> Result<(), Error> {
+    if offset_kind != OFFSETS_BYTES && offset_kind != OFFSETS_UTF16 {
+        return Err(Error::new(
+            ErrorCode::InvalidArgument,
+            format!("unknown offset kind {offset_kind}"),
+        ));
+    }
+    Ok(())
+}
+
+fn buffer_len(rows: usize, cols: usize) -> Result<usize, Error> {
+    rows.checked_mul(cols).ok_or_else(|| {
+        Error::new(
+            ErrorCode::InvalidArgument,
+            format!("{rows} x {cols} overflows"),
+        )
+    })
+}
+
+fn tokenizer(handle: Handle) -> Result<Arc<Tokenizer>, Error> {
+    registry::get(handle).ok_or_else(|| {
+        if handle == INVALID_HANDLE {
+            Error::new(ErrorCode::NotInitialized, "tokenizer is not initialized")
+        } else {
+            Error::new(
+                ErrorCode::NotInitialized,
+                format!("unknown tokenizer handle {handle}"),
+            )
         }
-        Err(_) => -2,
+    })
+}
+
+/// A `.model` file, or one starting with the key of `ModelProto.pieces` (field 1, length-delimited),
+/// unless it looks like a JSON object: `0x0A` is also a newline that may lead `tokenizer.json`.
+/// Everything else is parsed as `tokenizer.json`, so unrelated files get the JSON parser's error.
+fn is_sentencepiece(path: &str, bytes: &[u8]) -> bool {
+    if bytes.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{') {
+        return false;
     }
+    std::path::Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("model"))
+        || bytes.first() == Some(&0x0A)
 }
 
-#[no_mangle]
-pub extern "C" fn tokenizer_encode(text: *const c_char, out_ids: *mut i32, max_len: usize) -> i32 {
-    let c_str = unsafe { CStr::from_ptr(text) };
-    let text_str = match c_str.to_str() {
-        Ok(s) => s,
-        Err(_) => return -1,
+fn load(path: *const c_char) -> Result<Tokenizer, Error> {
+    let path_str = str_arg(path, "path")?;
+    let bytes = std::fs::read(path_str)
+        .map_err(|e| Error::io(e, format_args!("cannot read {path_str}")))?;
+    let tokenizer = if is_sentencepiece(path_str, &bytes) {
+        sentencepiece::from_bytes(&bytes)
+            .map_err(|e| Error::new(e.code, format!("cannot parse {path_str}: {}", e.message)))?
+    } else {
+        Tokenizer::from_bytes(bytes).map_err(|e| {
+            Error::new(
+                ErrorCode::JsonParse,
+                format!("cannot parse {path_str}: {e}"),
+            )
+        })?
     };
-    let tokenizer = match TOKENIZER.get() {
-        Some(t) => t,
-        None => return -2,
-    };
-    let encoding = match tokenizer.encode(text_str, true) {
-        Ok(enc) => enc,
-        Err(_) => return -3,
+    log::log(Level::Information, || {
+        format!(
+            "loaded {path_str} with {} tokens",
+            tokenizer.get_vocab_size(true)
+        )
+    });
+    Ok(tokenizer)
+}
+
+fn encode(
+    handle: Handle,
+    text: *const c_char,
+    out_ids: *mut i32,
+    max_len: usize,
+) -> Result<i32, Error> {
+    let text_str = str_arg(text, "text")?;
+    let tokenizer = tokenizer(handle)?;
+    let truncation = TruncationParams {
+        max_length: max_len,
+        ..Default::default()
     };
+    let encoding = encoding::encode(&tokenizer, text_str, true, Some(&truncation))?;
     let ids = encoding.get_ids();
     let ids_i32: Vec<i32> = ids.iter().map(|&id| id as i32).collect();
-    let len = ids_i32.len().min(max_len);
-    println!("[tokenizer_rust] encode | text='{}' | ids_len={} | first_ids={:?}", text_str.chars().take(30).collect::<String>(), len, &ids_i32[..len.min(10)]);
-    unsafe {
-        ptr::copy_nonoverlapping(ids_i32.as_ptr(), out_ids, len);
+    let len = ids_i32.len();
+    if len > max_len {
+        return Err(Error::new(
+            ErrorCode::BufferTooSmall,
+            format!("{len} special tokens do not fit in max_len {max_len}"),
+        ));
     }
-    len as i32
+    log::log(Level::Trace, || {
+        format!("encoded {} bytes into {len} ids", text_str.len())
+    });
+    out_slice(out_ids, len, "out_ids")?.copy_from_slice(&ids_i32);
+    Ok(len as i32)
 }
 
+fn decode_text(
+    handle: Handle,
+    ids: *const i32,
+    len: usize,
+    skip_special_tokens: bool,
+) -> Result<String, Error> {
+    let tokenizer = tokenizer(handle)?;
+    let ids_slice = in_slice(ids, len, "ids")?;
+    let tokens: Vec<u32> = ids_slice.iter().map(|&id| id as u32).collect();
+    tokenizer
+        .decode(&tokens, skip_special_tokens)
+        .map_err(|e| Error::new(ErrorCode::DecodeFailed, e.to_string()))
+}
+
+fn decode(handle: Handle, ids: *const i32, len: usize) -> Result<*mut c_char, Error> {
+    let text = decode_text(handle, ids, len, true)?;
+    CString::new(text)
+        .map(CString::into_raw)
+        .map_err(|e| Error::new(ErrorCode::DecodeFailed, e.to_string()))
+}
+
+/// Loads a `tokenizer.json` or SentencePiece `tokenizer.model` and makes it the default
+/// for the handle-less API.
+/// Calling it again replaces the previous default tokenizer.
+#[no_mangle]
+pub extern "C" fn tokenizer_init(path: *const c_char) -> i32 {
+    status(|| {
+        let previous = registry::set_default(registry::insert(load(path)?)?);
+        if previous != INVALID_HANDLE {
+            registry::remove(previous);
+        }
+        Ok(0)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_encode(text: *const c_char, out_ids: *mut i32, max_len: usize) -> i32 {
+    status(|| encode(registry::default_handle(), text, out_ids, max_len))
+}
+
+/// Returns null on failure; see `tokenizer_last_error_message`.
+/// The string must be released with `tokenizer_free_string`.
 #[no_mangle]
 pub extern "C" fn tokenizer_decode(ids: *const i32, len: usize) -> *mut c_char {
-    let tokenizer = match TOKENIZER.get() {
-        Some(t) => t,
-        None => return ptr::null_mut(),
-    };
-    let ids_slice = unsafe { std::slice::from_raw_parts(ids, len) };
-    let tokens: Vec<u32> = ids_slice.iter().map(|&id| id as u32).collect();
-    match tokenizer.decode(&tokens, true) {
-        Ok(text) => CString::new(text).unwrap().into_raw(),
-        Err(_) => ptr::null_mut(),
-    }
+    or_null(|| decode(registry::default_handle(), ids, len))
 }
 
+/// Drops every loaded tokenizer, including the default one, and every handle created from them
+/// or independently: streams, chat templates, stop matchers, embedders, caches and indexes.
+/// Calls already running on a handle finish first.
 #[no_mangle]
 pub extern "C" fn tokenizer_cleanup() {
-    // OnceCell nie pozwala na drop, ale można dodać logikę jeśli trzeba
-} 
\ No newline at end of file
+    let _ = guarded(|| {
+        stream::clear();
+        chat::clear();
+        stop::clear();
+        embed::clear();
+        cache::clear();
+        bm25::clear();
+        hnsw::clear();
+        registry::clear();
+        Ok(())
+    });
+}
+
+/// Loads a tokenizer into the registry. Returns `0` if it could not be loaded;
+/// see `tokenizer_last_error_code`.
+#[no_mangle]
+pub extern "C" fn tokenizer_create(path: *const c_char) -> Handle {
+    guarded(|| load(path).and_then(registry::insert)).unwrap_or_else(|e| {
+        error::report(e);
+        INVALID_HANDLE
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_handle(
+    handle: Handle,
+    text: *const c_char,
+    out_ids: *mut i32,
+    max_len: usize,
+) -> i32 {
+    status(|| encode(handle, text, out_ids, max_len))
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_decode_handle(
+    handle: Handle,
+    ids: *const i32,
+    len: usize,
+) -> *mut c_char {
+    or_null(|| decode(handle, ids, len))
+}
+
+/// Releases a string returned by `tokenizer_decode` or `tokenizer_decode_handle`. Null is ignored.
+#[no_mangle]
+pub extern "C" fn tokenizer_free_string(s: *mut c_char) {
+    let _ = guarded(|| {
+        if !s.is_null() {
+            drop(unsafe { CString::from_raw(s) });
+        }
+        Ok(())
+    });
+}
+
+/// Decodes into a caller-owned buffer, so no memory crosses the allocator boundary.
+/// Writes UTF-8 without a terminating NUL and returns the number of bytes written.
+/// The required size is always stored in `out_required`; if it exceeds `buf_len`,
+/// nothing is written and `BufferTooSmall` is returned.
+#[no_mangle]
+pub extern "C" fn tokenizer_decode_into(
+    handle: Handle,
+    ids: *const i32,
+    len: usize,
+    skip_special_tokens: bool,
+    out_buf: *mut u8,
+    buf_len: usize,
+    out_required: *mut usize,
+) -> i32 {
+    status(|| {
+        let out_required = out_ref(out_required, "out_required")?;
+        let text = decode_text(handle, ids, len, skip_special_tokens)?;
+        write_utf8(&text, out_buf, buf_len, out_required)
+    })
+}
+
+/// Loads `path` and swaps it in behind `handle`, or behind the default tokenizer when `handle` is `0`.
+/// The swap replaces the `Arc` in the registry, so encodes already running keep their `Arc`
+/// to the old tokenizer and finish on it; later calls see the new one.
+/// On failure the old tokenizer stays in place.
+///
+/// Streams, embedders and BM25 analyzers created from the handle keep the tokenizer they were
+/// created with; recreate them to pick up the reloaded one.
+#[no_mangle]
+pub extern "C" fn tokenizer_reload(handle: Handle, path: *const c_char) -> i32 {
+    status(|| {
+        let handle = match handle {
+            INVALID_HANDLE => registry::default_handle(),
+            handle => handle,
+        };
+        let tokenizer = load(path)?;
+        if registry::replace(handle, tokenizer)? {
+            Ok(ErrorCode::Ok as i32)
+        } else {
+            Err(Error::new(
+                ErrorCode::NotInitialized,
+                format!("unknown tokenizer handle {handle}"),
+            ))
+        }
+    })
+}
+
+/// Counter that grows every time a tokenizer is reloaded or the default one is set or freed.
+/// Callers caching token counts compare it to detect that the tokenizer was swapped.
+#[no_mangle]
+pub extern "C" fn tokenizer_generation() -> u64 {
+    guarded(|| Ok(registry::generation())).unwrap_or(0)
+}
+
+/// Releases a handle. Calls already running on it finish before the tokenizer is dropped.
+#[no_mangle]
+pub extern "C" fn tokenizer_free(handle: Handle) -> i32 {
+    status(|| {
+        if registry::remove(handle) {
+            Ok(ErrorCode::Ok as i32)
+        } else {
+            Err(Error::new(
+                ErrorCode::NotInitialized,
+                format!("unknown tokenizer handle {handle}"),
+            ))
+        }
+    })
+}
+
+/// Code of the most recent failure on the calling thread.
+#[no_mangle]
+pub extern "C" fn tokenizer_last_error_code() -> ErrorCode {
+    guarded(|| Ok(error::last_error_code())).unwrap_or(ErrorCode::Panic)
+}
+
+/// Message of the most recent failure on the calling thread, or null if nothing failed yet.
+/// The string is owned by the library and stays valid until the next failure on this thread.
+#[no_mangle]
+pub extern "C" fn tokenizer_last_error_message() -> *const c_char {
+    guarded(|| Ok(error::last_error_message())).unwrap_or(ptr::null())
+}
+
+/// Routes the crate's log messages to `callback`; null disables logging, which is the default.
+/// The callback may be invoked from any thread that calls into the library.
+#[no_mangle]
+pub extern "C" fn tokenizer_set_log_callback(callback: Option<log::Callback>) {
+    let _ = guarded(|| {
+        log::set_callback(callback);
+        Ok(())
+    });
+}
+
+/// Drops messages below `level`, using .NET `LogLevel` numbering. Defaults to `Information`.
+#[no_mangle]
+pub extern "C" fn tokenizer_set_log_level(level: i32) {
+    let _ = guarded(|| {
+        log::set_min_level(level);
+        Ok(())
+    });
+}
+
+/// Returns the number of tokens `text` encodes to, or a negative error code.
+#[no_mangle]
+pub extern "C" fn tokenizer_count_tokens(text: *const c_char, add_special_tokens: bool) -> i32 {
+    tokenizer_count_tokens_handle(registry::default_handle(), text, add_special_tokens)
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_count_tokens_handle(
+    handle: Handle,
+    text: *const c_char,
+    add_special_tokens: bool,
+) -> i32 {
+    status(|| {
+        let text = str_arg(text, "text")?;
+        let count = encoding::count(&*tokenizer(handle)?, text, add_special_tokens)?;
+        Ok(count as i32)
+    })
+}
+
+/// Counts tokens of `count` texts in parallel, writing one count per text into `out_counts`.
+#[no_mangle]
+pub extern "C" fn tokenizer_count_tokens_batch(
+    handle: Handle,
+    texts: *const *const c_char,
+    count: usize,
+    add_special_tokens: bool,
+    out_counts: *mut i32,
+) -> i32 {
+    status(|| {
+        let tokenizer = tokenizer(handle)?;
+        let inputs = in_slice(texts, count, "texts")?
+            .iter()
+            .map(|&text| str_arg(text, "text"))
+            .collect::<Result<Vec<_>, _>>()?;
+        let out_counts = out_slice(out_counts, count, "out_counts")?;
+        let counts = encoding::count_batch(&tokenizer, inputs, add_special_tokens)?;
+        for (dst, n) in out_counts.iter_mut().zip(counts) {
+            *dst = n as i32;
+        }
+        Ok(0)
+    })
+}
+
+/// Encodes `count` texts into row-major `[count, seq_len]` buffers ready for ONNX `int64` tensors.
+/// `capacity` is the number of elements in each output buffer; `out_token_type_ids` may be null.
+/// The sequence length is always written to `out_seq_len`, so after `BufferTooSmall`
+/// the caller can allocate `count * seq_len` and retry.
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_batch(
+    handle: Handle,
+    texts: *const *const c_char,
+    count: usize,
+    options: *const BatchEncodeOptions,
+    out_input_ids: *mut i64,
+    out_attention_mask: *mut i64,
+    out_token_type_ids: *mut i64,
+    capacity: usize,
+    out_seq_len: *mut usize,
+) -> i32 {
+    status(|| {
+        let tokenizer = tokenizer(handle)?;
+        let options = *unsafe { options.as_ref() }.ok_or_else(|| null_error("options"))?;
+        let out_seq_len = out_ref(out_seq_len, "out_seq_len")?;
+        let text_ptrs = in_slice(texts, count, "texts")?;
+        let inputs = text_ptrs
+            .iter()
+            .map(|&text| str_arg(text, "text"))
+            .collect::<Result<Vec<_>, _>>()?;
+        let encodings = batch::encode(&tokenizer, inputs, &options)?;
+        let seq_len = encodings.first().map_or(0, |e| e.len());
+        *out_seq_len = seq_len;
+        let total = buffer_len(count, seq_len)?;
+        if total > capacity {
+            return Err(Error::new(
+                ErrorCode::BufferTooSmall,
+                format!("{count} x {seq_len} tokens do not fit in capacity {capacity}"),
+            ));
+        }
+
+        let input_ids = out_slice(out_input_ids, total, "out_input_ids")?;
+        let attention_mask = out_slice(out_attention_mask, total, "out_attention_mask")?;
+        let mut token_type_ids = opt_out_slice(out_token_type_ids, total);
+        for (row, encoding) in encodings.iter().enumerate() {
+            let range = row * seq_len..(row + 1) * seq_len;
+            for (dst, &id) in input_ids[range.clone()].iter_mut().zip(encoding.get_ids()) {
+                *dst = id as i64;
+            }
+            for (dst, &m) in attention_mask[range.clone()]
+                .iter_mut()
+                .zip(encoding.get_attention_mask())
+            {
+                *dst = m as i64;
+            }
+            if let Some(type_ids) = token_type_ids.as_deref_mut() {
+                for (dst, &t) in type_ids[range].iter_mut().zip(encoding.get_type_ids()) {
+                    *dst = t as i64;
+                }
+            }
+        }
+        Ok(0)
+    })
+}
+
+/// Encodes `text` and fills parallel per-token arrays, truncating to `max_len` tokens while keeping
+/// special tokens. `out_offsets` receives `(start, end)` pairs, in bytes or UTF-16 code units
+/// depending on `offset_kind`; `out_word_ids` uses `-1` for tokens outside any word.
+/// Every output buffer except `out_ids` may be null. Returns the number of tokens written.
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_ex(
+    handle: Handle,
+    text: *const c_char,
+    add_special_tokens: bool,
+    offset_kind: u32,
+    out_ids: *mut i32,
+    out_offsets: *mut u32,
+    out_word_ids: *mut i32,
+    out_special_tokens_mask: *mut u8,
+    max_len: usize,
+) -> i32 {
+    status(|| {
+        let text_str = str_arg(text, "text")?;
+        check_offset_kind(offset_kind)?;
+        let tokenizer = tokenizer(handle)?;
+        let truncation = TruncationParams {
+            max_length: max_len,
+            ..Default::default()
+        };
+        let encoding =
+            encoding::encode(&tokenizer, text_str, add_special_tokens, Some(&truncation))?;
+        let len = encoding.len();
+        if len > max_len {
+            return Err(Error::new(
+                ErrorCode::BufferTooSmall,
+                format!("{len} special tokens do not fit in max_len {max_len}"),
+            ));
+        }
+
+        let ids = out_slice(out_ids, len, "out_ids")?;
+        for (dst, &id) in ids.iter_mut().zip(encoding.get_ids()) {
+            *dst = id as i32;
+        }
+        if let Some(out) = opt_out_slice(out_offsets, 2 * len) {
+            let utf16;
+            let offsets = if offset_kind == OFFSETS_UTF16 {
+                utf16 = encoding::utf16_offsets(text_str, encoding.get_offsets());
+                &utf16[..]
+            } else {
+                encoding.get_offsets()
+            };
+            for (dst, &(start, end)) in out.chunks_exact_mut(2).zip(offsets) {
+                dst[0] = start as u32;
+                dst[1] = end as u32;
+            }
+        }
+        if let Some(out) = opt_out_slice(out_word_ids, len) {
+            for (dst, word) in out.iter_mut().zip(encoding.get_word_ids()) {
+                *dst = word.map_or(-1, |w| w as i32);
+            }
+        }
+        if let Some(out) = opt_out_slice(out_special_tokens_mask, len) {
+            for (dst, &special) in out.iter_mut().zip(encoding.get_special_tokens_mask()) {
+                *dst = special as u8;
+            }
+        }
+        Ok(len as i32)
+    })
+}
+
+/// Splits `text` into windows of at most `max_len` tokens, each with its own special tokens,
+/// where consecutive windows share `stride` content tokens. `out_ids` is a row-major
+/// `[max_windows, max_len]` buffer; unused positions are filled with the pad id and
+/// `out_window_lens` receives the length of every row. `out_full_len` receives the untruncated
+/// length and `out_window_count` the number of windows, even when `BufferTooSmall` is returned.
+/// Passing `max_windows = 1` gives plain truncation together with the information that text was lost.
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_windows(
+    handle: Handle,
+    text: *const c_char,
+    max_len: usize,
+    stride: usize,
+    out_ids: *mut i32,
+    out_window_lens: *mut usize,
+    max_windows: usize,
+    out_full_len: *mut usize,
+    out_window_count: *mut usize,
+) -> i32 {
+    status(|| {
+        let text_str = str_arg(text, "text")?;
+        let out_full_len = out_ref(out_full_len, "out_full_len")?;
+        let out_window_count = out_ref(out_window_count, "out_window_count")?;
+        let tokenizer = tokenizer(handle)?;
+        let windows = encoding::encode_windows(&tokenizer, text_str, true, max_len, stride)?;
+        let count = windows.encodings.len();
+        *out_full_len = windows.full_len;
+        *out_window_count = count;
+        if count > max_windows {
+            return Err(Error::new(
+                ErrorCode::BufferTooSmall,
+                format!("{count} windows do not fit in max_windows {max_windows}"),
+            ));
+        }
+
+        let (pad_id, _) = encoding::pad_token(&tokenizer);
+        let ids = out_slice(out_ids, buffer_len(max_windows, max_len)?, "out_ids")?;
+        let lens = out_slice(out_window_lens, max_windows, "out_window_lens")?;
+        ids.fill(pad_id as i32);
+        lens.fill(0);
+        for ((row, len), window) in ids
+            .chunks_exact_mut(max_len)
+            .zip(lens.iter_mut())
+            .zip(&windows.encodings)
+        {
+            for (dst, &id) in row.iter_mut().zip(window.get_ids()) {
+                *dst = id as i32;
+            }
+            *len = window.len();
+        }
+        Ok(0)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::{PADDING_LONGEST, TRUNCATION_RIGHT};
+    use std::thread;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tokenizer.json");
+    const NUL_TOKEN: i32 = 37;
+    // Same vocabulary with truncation to 4 tokens and fixed padding to 8 in `tokenizer.json`.
+    const CONFIGURED: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/testdata/tokenizer_configured.json"
+    );
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    fn create() -> Handle {
+        let handle = tokenizer_create(c(FIXTURE).as_ptr());
+        assert_ne!(handle, INVALID_HANDLE);
+        handle
+    }
+
+    fn last_message() -> String {
+        let message = tokenizer_last_error_message();
+        assert!(!message.is_null());
+        unsafe { CStr::from_ptr(message) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn batch_options() -> BatchEncodeOptions {
+        BatchEncodeOptions {
+            add_special_tokens: true,
+            padding: PADDING_LONGEST,
+            pad_to_length: 0,
+            pad_left: false,
+            truncation: TRUNCATION_RIGHT,
+            max_length: 8,
+        }
+    }
+
+    #[test]
+    fn null_pointers_are_rejected() {
+        let handle = create();
+        let text = c("hello world");
+        let mut ids = [0i32; 8];
+        let (mut n, mut count) = (0usize, 0usize);
+        let null = ErrorCode::NullPointer as i32;
+
+        assert_eq!(tokenizer_init(ptr::null()), null);
+        assert_eq!(tokenizer_create(ptr::null()), INVALID_HANDLE);
+        assert_eq!(tokenizer_last_error_code(), ErrorCode::NullPointer);
+        assert_eq!(
+            tokenizer_encode_handle(handle, ptr::null(), ids.as_mut_ptr(), 8),
+            null
+        );
+        assert_eq!(
+            tokenizer_encode_handle(handle, text.as_ptr(), ptr::null_mut(), 8),
+            null
+        );
+        assert!(tokenizer_decode_handle(handle, ptr::null(), 3).is_null());
+        assert_eq!(
+            tokenizer_decode_into(handle, [5].as_ptr(), 1, true, ptr::null_mut(), 8, &mut n),
+            null
+        );
+        assert_eq!(
+            tokenizer_encode_batch(
+                handle,
+                ptr::null(),
+                2,
+                &batch_options(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                0,
+                &mut n,
+            ),
+            null
+        );
+        assert_eq!(
+            tokenizer_encode_windows(
+                handle,
+                text.as_ptr(),
+                8,
+                0,
+                ids.as_mut_ptr(),
+                ptr::null_mut(),
+                1,
+                &mut n,
+                &mut count,
+            ),
+            null
+        );
+        assert!(last_message().contains("out_window_lens"));
+        tokenizer_free_string(ptr::null_mut());
+        tokenizer_free(handle);
+    }
+
+    #[test]
+    fn empty_inputs_may_be_null() {
+        let handle = create();
+        let text = c("hello");
+        let mut ids = [0i32; 4];
+        let mut n = usize::MAX;
+        let s = tokenizer_decode_handle(handle, ptr::null(), 0);
+        assert_eq!(unsafe { CStr::from_ptr(s) }.to_bytes(), b"");
+        tokenizer_free_string(s);
+        assert_eq!(
+            tokenizer_decode_into(handle, ptr::null(), 0, true, ptr::null_mut(), 0, &mut n),
+            0
+        );
+        assert_eq!(n, 0);
+        assert_eq!(
+            tokenizer_encode_ex(
+                handle,
+                text.as_ptr(),
+                true,
+                OFFSETS_UTF16,
+                ids.as_mut_ptr(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                4,
+            ),
+            3
+        );
+        tokenizer_free(handle);
+    }
+
+    #[test]
+    fn invalid_utf8_is_reported() {
+        let handle = create();
+        let bytes = [b'a', 0xC3, 0x28, 0];
+        let mut ids = [0i32; 8];
+        assert_eq!(
+            tokenizer_encode_handle(handle, bytes.as_ptr().cast(), ids.as_mut_ptr(), 8),
+            ErrorCode::InvalidUtf8 as i32
+        );
+        assert!(last_message().contains("UTF-8"));
+        tokenizer_free(handle);
+    }
+
+    #[test]
+    fn load_failures_are_classified() {
+        assert_eq!(
+            tokenizer_create(c("/definitely/missing/tokenizer.json").as_ptr()),
+            INVALID_HANDLE
+        );
+        assert_eq!(tokenizer_last_error_code(), ErrorCode::FileNotFound);
+        let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        assert_eq!(tokenizer_create(c(manifest).as_ptr()), INVALID_HANDLE);
+        assert_eq!(tokenizer_last_error_code(), ErrorCode::JsonParse);
+        assert!(last_message().contains("Cargo.toml"));
+        assert!(!last_message().contains("SentencePiece"));
+
+        let corrupt = std::env::temp_dir().join(format!("corrupt-{}.model", std::process::id()));
+        std::fs::write(&corrupt, b"\xff\xff\xff").unwrap();
+        assert_eq!(
+            tokenizer_create(c(corrupt.to_str().unwrap()).as_ptr()),
+            INVALID_HANDLE
+        );
+        assert_eq!(tokenizer_last_error_code(), ErrorCode::JsonParse);
+        assert!(last_message().contains("SentencePiece"));
+        std::fs::remove_file(corrupt).unwrap();
+    }
+
+    #[test]
+    fn json_starting_with_a_newline_is_not_sentencepiece() {
+        let json = std::env::temp_dir().join(format!("newline-{}.json", std::process::id()));
+        let mut bytes = b"\n".to_vec();
+        bytes.extend(std::fs::read(FIXTURE).unwrap());
+        std::fs::write(&json, bytes).unwrap();
+        let handle = tokenizer_create(c(json.to_str().unwrap()).as_ptr());
+        std::fs::remove_file(json).unwrap();
+        assert_ne!(handle, INVALID_HANDLE);
+        tokenizer_free(handle);
+    }
+
+    #[test]
+    fn sentencepiece_models_load_through_the_same_entry_points() {
+        let model = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/sentencepiece.model");
+        let handle = tokenizer_create(c(model).as_ptr());
+        assert_ne!(handle, INVALID_HANDLE);
+        let text = c("hello world");
+        assert_eq!(
+            tokenizer_count_tokens_handle(handle, text.as_ptr(), true),
+            3
+        );
+        tokenizer_free(handle);
+    }
+
+    #[test]
+    fn handles_keep_independent_tokenizers() {
+        let bert = create();
+        let model = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/sentencepiece.model");
+        let sentencepiece = tokenizer_create(c(model).as_ptr());
+        assert_ne!(sentencepiece, INVALID_HANDLE);
+        assert_ne!(bert, sentencepiece);
+
+        let text = c("hello world");
+        let encode = |handle| {
+            let mut ids = [0i32; 8];
+            let n = tokenizer_encode_handle(handle, text.as_ptr(), ids.as_mut_ptr(), 8);
+            assert!(n > 0);
+            ids[..n as usize].to_vec()
+        };
+        let bert_ids = encode(bert);
+        assert_eq!(bert_ids, [2, 5, 6, 3]);
+        assert_ne!(encode(sentencepiece), bert_ids);
+
+        assert_eq!(tokenizer_free(sentencepiece), 0);
+        assert_eq!(encode(bert), bert_ids);
+        tokenizer_free(bert);
+    }
+
+    #[test]
+    fn unknown_and_freed_handles_are_rejected() {
+        let handle = create();
+        assert_eq!(tokenizer_free(handle), 0);
+        assert_eq!(tokenizer_free(handle), ErrorCode::NotInitialized as i32);
+        let mut ids = [0i32; 8];
+        assert_eq!(
+            tokenizer_encode_handle(handle, c("hello").as_ptr(), ids.as_mut_ptr(), 8),
+            ErrorCode::NotInitialized as i32
+        );
+        assert!(tokenizer_decode_handle(u64::MAX, ids.as_ptr(), 1).is_null());
+    }
+
+    #[test]
+    fn interior_nul_in_decoded_text_is_an_error() {
+        let handle = create();
+        let ids = [5, NUL_TOKEN, 6];
+        assert!(tokenizer_decode_handle(handle, ids.as_ptr(), ids.len()).is_null());
+        assert_eq!(tokenizer_last_error_code(), ErrorCode::DecodeFailed);
+
+        let mut buf = [0u8; 32];
+        let mut required = 0;
+        let written = tokenizer_decode_into(
+            handle,
+            ids.as_ptr(),
+            ids.len(),
+            true,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut required,
+        );
+        assert_eq!(written as usize, required);
+        assert!(buf[..required].contains(&0));
+        tokenizer_free(handle);
+    }
+
+    #[test]
+    fn negative_and_out_of_range_ids_do_not_crash() {
+        let handle = create();
+        let ids = [-1, i32::MIN, i32::MAX, 5];
+        let s = tokenizer_decode_handle(handle, ids.as_ptr(), ids.len());
+        assert!(!s.is_null());
+        tokenizer_free_string(s);
+        tokenizer_free(handle);
+    }
+
+    #[test]
+    fn last_error_is_per_thread_with_stable_codes() {
+        assert_eq!(
+            [
+                ErrorCode::InvalidUtf8,
+                ErrorCode::NotInitialized,
+                ErrorCode::BufferTooSmall,
+                ErrorCode::FileNotFound,
+                ErrorCode::NullPointer,
+            ]
+            .map(|code| code as i32),
+            [-1, -2, -4, -6, -9]
+        );
+        let mut ids = [0i32; 4];
+        assert_eq!(
+            tokenizer_encode_handle(u64::MAX, c("x").as_ptr(), ids.as_mut_ptr(), 4),
+            ErrorCode::NotInitialized as i32
+        );
+        thread::spawn(|| {
+            assert!(tokenizer_last_error_message().is_null());
+            assert_eq!(tokenizer_create(ptr::null()), INVALID_HANDLE);
+            assert_eq!(tokenizer_last_error_code(), ErrorCode::NullPointer);
+        })
+        .join()
+        .unwrap();
+        assert_eq!(tokenizer_last_error_code(), ErrorCode::NotInitialized);
+        assert!(last_message().contains("handle"));
+    }
+
+    #[test]
+    fn decoded_strings_round_trip_through_both_apis() {
+        let handle = create();
+        let ids = [2, 7, 8, 9, 10, 3];
+        let s = tokenizer_decode_handle(handle, ids.as_ptr(), ids.len());
+        assert!(!s.is_null());
+        assert_eq!(
+            unsafe { CStr::from_ptr(s) }.to_str().unwrap(),
+            "the quick brown fox"
+        );
+        tokenizer_free_string(s);
+
+        let mut buf = [0xAAu8; 19];
+        let mut required = 0;
+        let written = tokenizer_decode_into(
+            handle,
+            ids.as_ptr(),
+            ids.len(),
+            true,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut required,
+        );
+        assert_eq!((written, required), (19, 19));
+        assert_eq!(&buf, b"the quick brown fox");
+        tokenizer_free(handle);
+    }
+
+    #[test]
+    fn panics_are_caught_and_reported() {
+        assert_eq!(status(|| panic!("boom")), ErrorCode::Panic as i32);
+        assert_eq!(last_message(), "boom");
+        assert!(or_null::<c_char>(|| panic!("{}", String::from("owned"))).is_null());
+        assert_eq!(last_message(), "owned");
+    }
+
+    #[test]
+    fn undersized_buffers_report_required_size() {
+        let handle = create();
+        let ids = [5, 6];
+        let mut buf = [0u8; 2];
+        let mut required = 0;
+        assert_eq!(
+            tokenizer_decode_into(
+                handle,
+                ids.as_ptr(),
+                2,
+                true,
+                buf.as_mut_ptr(),
+                2,
+                &mut required
+            ),
+            ErrorCode::BufferTooSmall as i32
+        );
+        assert_eq!(required, "hello world".len());
+
+        let texts = [c("hello world"), c("the quick brown fox")];
+        let ptrs: Vec<_> = texts.iter().map(|t| t.as_ptr()).collect();
+        let mut input_ids = [0i64; 4];
+        let mut mask = [0i64; 4];
+        let mut seq_len = 0;
+        assert_eq!(
+            tokenizer_encode_batch(
+                handle,
+                ptrs.as_ptr(),
+                ptrs.len(),
+                &batch_options(),
+                input_ids.as_mut_ptr(),
+                mask.as_mut_ptr(),
+                ptr::null_mut(),
+                input_ids.len(),
+                &mut seq_len,
+            ),
+            ErrorCode::BufferTooSmall as i32
+        );
+        assert_eq!(seq_len, 6);
+
+        let mut out = [0i32; 2];
+        assert_eq!(
+            tokenizer_encode_handle(handle, c("hello").as_ptr(), out.as_mut_ptr(), 1),
+            ErrorCode::BufferTooSmall as i32
+        );
+        tokenizer_free(handle);
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        let handle = create();
+        let text = c("a b c the quick brown fox");
+        let mut ids = [0i32; 8];
+        let mut lens = [0usize; 1];
+        let (mut full, mut count) = (0, 0);
+        assert_eq!(
+            tokenizer_encode_windows(
+                handle,
+                text.as_ptr(),
+                4,
+                2,
+                ids.as_mut_ptr(),
+                lens.as_mut_ptr(),
+                1,
+                &mut full,
+                &mut count,
+            ),
+            ErrorCode::InvalidArgument as i32
+        );
+        assert_eq!(
+            tokenizer_encode_windows(
+                handle,
+                text.as_ptr(),
+                usize::MAX / 2,
+                0,
+                ids.as_mut_ptr(),
+                lens.as_mut_ptr(),
+                usize::MAX,
+                &mut full,
+                &mut count,
+            ),
+            ErrorCode::InvalidArgument as i32
+        );
+        assert!(last_message().contains("overflows"));
+
+        let options = BatchEncodeOptions {
+            padding: 7,
+            ..batch_options()
+        };
+        let ptrs = [text.as_ptr()];
+        let mut seq_len = 0;
+        assert_eq!(
+            tokenizer_encode_batch(
+                handle,
+                ptrs.as_ptr(),
+                1,
+                &options,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                0,
+                &mut seq_len,
+            ),
+            ErrorCode::InvalidArgument as i32
+        );
+        assert_eq!(
+            tokenizer_encode_ex(
+                handle,
+                text.as_ptr(),
+                true,
+                9,
+                ids.as_mut_ptr(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                8,
+            ),
+            ErrorCode::InvalidArgument as i32
+        );
+        tokenizer_free(handle);
+    }
+
+    #[test]
+    fn batch_encoding_writes_padded_ids_and_masks() {
+        let handle = create();
+        let texts = [c("hello world"), c("the quick brown fox")];
+        let ptrs: Vec<_> = texts.iter().map(|t| t.as_ptr()).collect();
+        let encode = |options: BatchEncodeOptions| {
+            let mut ids = [-1i64; 16];
+            let mut mask = [-1i64; 16];
+            let mut type_ids = [-1i64; 16];
+            let mut seq_len = 0;
+            assert_eq!(
+                tokenizer_encode_batch(
+                    handle,
+                    ptrs.as_ptr(),
+                    ptrs.len(),
+                    &options,
+                    ids.as_mut_ptr(),
+                    mask.as_mut_ptr(),
+                    type_ids.as_mut_ptr(),
+                    16,
+                    &mut seq_len,
+                ),
+                0
+            );
+            let total = 2 * seq_len;
+            assert!(type_ids[..total].iter().all(|&t| t == 0));
+            (seq_len, ids[..total].to_vec(), mask[..total].to_vec())
+        };
+
+        let (seq_len, ids, mask) = encode(batch_options());
+        assert_eq!(seq_len, 6);
+        assert_eq!(ids, [2, 5, 6, 3, 0, 0, 2, 7, 8, 9, 10, 3]);
+        assert_eq!(mask, [1, 1, 1, 1, 0, 0, 1, 1, 1, 1, 1, 1]);
+
+        let (seq_len, ids, mask) = encode(BatchEncodeOptions {
+            padding: crate::batch::PADDING_FIXED,
+            pad_to_length: 7,
+            pad_left: true,
+            ..batch_options()
+        });
+        assert_eq!(seq_len, 7);
+        assert_eq!(ids, [0, 0, 0, 2, 5, 6, 3, 0, 2, 7, 8, 9, 10, 3]);
+        assert_eq!(mask, [0, 0, 0, 1, 1, 1, 1, 0, 1, 1, 1, 1, 1, 1]);
+
+        let (seq_len, ids, mask) = encode(BatchEncodeOptions {
+            max_length: 4,
+            ..batch_options()
+        });
+        assert_eq!(seq_len, 4);
+        assert_eq!(ids, [2, 5, 6, 3, 2, 7, 8, 3]);
+        assert_eq!(mask, [1; 8]);
+        tokenizer_free(handle);
+    }
+
+    #[test]
+    fn configured_truncation_and_padding_do_not_leak_into_batches() {
+        let configured = tokenizer_create(c(CONFIGURED).as_ptr());
+        assert_ne!(configured, INVALID_HANDLE);
+        let texts = [c("hello world"), c("the quick brown fox jumps")];
+        let ptrs: Vec<_> = texts.iter().map(|t| t.as_ptr()).collect();
+        let mut ids = [-1i64; 16];
+        let mut mask = [-1i64; 16];
+        let mut seq_len = 0;
+        assert_eq!(
+            tokenizer_encode_batch(
+                configured,
+                ptrs.as_ptr(),
+                2,
+                &batch_options(),
+                ids.as_mut_ptr(),
+                mask.as_mut_ptr(),
+                ptr::null_mut(),
+                16,
+                &mut seq_len,
+            ),
+            0
+        );
+        assert_eq!(seq_len, 7);
+        assert_eq!(ids[..14], [2, 5, 6, 3, 0, 0, 0, 2, 7, 8, 9, 10, 11, 3]);
+        assert_eq!(mask[..14], [1, 1, 1, 1, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1]);
+        tokenizer_free(configured);
+    }
+
+    #[test]
+    fn encode_ex_reports_offsets_word_ids_and_special_tokens() {
+        let handle = create();
+        let text = c("zażółć gęślą");
+        let encode = |offset_kind| {
+            let mut ids = [0i32; 8];
+            let mut offsets = [u32::MAX; 16];
+            let mut words = [0i32; 8];
+            let mut special = [9u8; 8];
+            let n = tokenizer_encode_ex(
+                handle,
+                text.as_ptr(),
+                true,
+                offset_kind,
+                ids.as_mut_ptr(),
+                offsets.as_mut_ptr(),
+                words.as_mut_ptr(),
+                special.as_mut_ptr(),
+                8,
+            );
+            assert_eq!(n, 4);
+            assert_eq!(ids[..4], [2, 15, 16, 3]);
+            assert_eq!(words[..4], [-1, 0, 1, -1]);
+            assert_eq!(special[..4], [1, 0, 0, 1]);
+            offsets[..8].to_vec()
+        };
+        assert_eq!(encode(OFFSETS_UTF16), [0, 0, 0, 6, 7, 12, 0, 0]);
+        assert_eq!(encode(OFFSETS_BYTES), [0, 0, 0, 10, 11, 19, 0, 0]);
+        tokenizer_free(handle);
+    }
+
+    #[test]
+    fn long_text_is_split_into_overlapping_windows() {
+        const SEP: i32 = 3;
+        let handle = create();
+        let text = c("the quick brown fox jumps over the lazy dog");
+        let (max_len, stride) = (6, 2);
+        let mut ids = [-1i32; 6 * 8];
+        let mut lens = [0usize; 8];
+        let (mut full_len, mut count) = (0, 0);
+        assert_eq!(
+            tokenizer_encode_windows(
+                handle,
+                text.as_ptr(),
+                max_len,
+                stride,
+                ids.as_mut_ptr(),
+                lens.as_mut_ptr(),
+                8,
+                &mut full_len,
+                &mut count,
+            ),
+            0
+        );
+        assert_eq!(full_len, 11);
+        assert!(count > 1);
+        let windows: Vec<&[i32]> = ids
+            .chunks_exact(max_len)
+            .zip(&lens)
+            .take(count)
+            .map(|(row, &len)| &row[..len])
+            .collect();
+        for window in &windows {
+            assert_eq!(window.first(), Some(&2));
+            assert_eq!(window.last(), Some(&SEP));
+        }
+        for pair in windows.windows(2) {
+            let (prev, next) = (&pair[0][1..pair[0].len() - 1], &pair[1][1..]);
+            assert_eq!(prev[prev.len() - stride..], next[..stride]);
+        }
+        let content: Vec<i32> = windows
+            .iter()
+            .flat_map(|w| &w[1..w.len() - 1])
+            .copied()
+            .collect();
+        assert_eq!(content.len(), 9 + (count - 1) * stride);
+
+        let mut out = [0i32; 4];
+        assert_eq!(
+            tokenizer_encode_handle(handle, text.as_ptr(), out.as_mut_ptr(), 4),
+            4
+        );
+        assert_eq!(out, [2, 7, 8, SEP]);
+        tokenizer_free(handle);
+    }
+
+    #[test]
+    fn windows_cover_text_past_a_configured_truncation() {
+        let text = c("the quick brown fox jumps over the lazy dog");
+        let windows = |handle| {
+            let mut ids = [-1i32; 6 * 8];
+            let mut lens = [0usize; 8];
+            let (mut full_len, mut count) = (0, 0);
+            assert_eq!(
+                tokenizer_encode_windows(
+                    handle,
+                    text.as_ptr(),
+                    6,
+                    2,
+                    ids.as_mut_ptr(),
+                    lens.as_mut_ptr(),
+                    8,
+                    &mut full_len,
+                    &mut count,
+                ),
+                0
+            );
+            (full_len, count, ids, lens)
+        };
+        let plain = create();
+        let configured = tokenizer_create(c(CONFIGURED).as_ptr());
+        let expected = windows(plain);
+        assert_eq!(expected.0, 11);
+        assert_eq!(windows(configured), expected);
+        tokenizer_free(plain);
+        tokenizer_free(configured);
+    }
+
+    #[test]
+    fn counts_match_encoded_lengths() {
+        let handle = create();
+        let texts = [c("hello world"), c(""), c("the quick brown fox")];
+        let ptrs: Vec<_> = texts.iter().map(|t| t.as_ptr()).collect();
+        let mut ids = [0i32; 16];
+        let mut counts = [0i32; 3];
+        assert_eq!(
+            tokenizer_count_tokens_batch(handle, ptrs.as_ptr(), 3, true, counts.as_mut_ptr()),
+            0
+        );
+        for (text, &count) in texts.iter().zip(&counts) {
+            let encoded = tokenizer_encode_handle(handle, text.as_ptr(), ids.as_mut_ptr(), 16);
+            assert_eq!(count, encoded);
+            assert_eq!(
+                tokenizer_count_tokens_handle(handle, text.as_ptr(), false),
+                count - 2
+            );
+        }
+        assert_eq!(
+            tokenizer_count_tokens_handle(handle, ptr::null(), true),
+            ErrorCode::NullPointer as i32
+        );
+        tokenizer_free(handle);
+    }
+
+    #[test]
+    fn counts_ignore_configured_truncation_and_padding() {
+        let handle = tokenizer_create(c(CONFIGURED).as_ptr());
+        let texts = [c("the quick brown fox jumps over the lazy dog"), c("hello")];
+        assert_eq!(
+            tokenizer_count_tokens_handle(handle, texts[0].as_ptr(), true),
+            11
+        );
+        assert_eq!(
+            tokenizer_count_tokens_handle(handle, texts[1].as_ptr(), false),
+            1
+        );
+        let ptrs: Vec<_> = texts.iter().map(|t| t.as_ptr()).collect();
+        let mut counts = [0i32; 2];
+        assert_eq!(
+            tokenizer_count_tokens_batch(handle, ptrs.as_ptr(), 2, true, counts.as_mut_ptr()),
+            0
+        );
+        assert_eq!(counts, [11, 3]);
+        tokenizer_free(handle);
+    }
+
+    #[test]
+    fn free_during_concurrent_encodes_is_safe() {
+        let handle = create();
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                thread::spawn(move || {
+                    let text = c("the quick brown fox jumps over the lazy dog");
+                    let mut ids = [0i32; 16];
+                    for _ in 0..200 {
+                        let n =
+                            tokenizer_encode_handle(handle, text.as_ptr(), ids.as_mut_ptr(), 16);
+                        assert!(n == 11 || n == ErrorCode::NotInitialized as i32);
+                    }
+                })
+            })
+            .collect();
+        tokenizer_free(handle);
+        for worker in workers {
+            worker.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn default_tokenizer_changes_bump_the_generation() {
+        let before = tokenizer_generation();
+        assert_eq!(tokenizer_init(c(FIXTURE).as_ptr()), 0);
+        let initialized = tokenizer_generation();
+        assert!(initialized > before);
+        assert_eq!(tokenizer_reload(INVALID_HANDLE, c(FIXTURE).as_ptr()), 0);
+        let reloaded = tokenizer_generation();
+        assert!(reloaded > initialized);
+        assert_eq!(tokenizer_free(registry::default_handle()), 0);
+        assert!(tokenizer_generation() > reloaded);
+        let mut ids = [0i32; 8];
+        assert_eq!(
+            tokenizer_encode(c("hello").as_ptr(), ids.as_mut_ptr(), 8),
+            ErrorCode::NotInitialized as i32
+        );
+    }
+
+    #[test]
+    fn reload_swaps_under_concurrent_encodes() {
+        let handle = create();
+        let before = tokenizer_generation();
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                thread::spawn(move || {
+                    let text = c("the quick brown fox jumps over the lazy dog");
+                    let mut ids = [0i32; 16];
+                    for _ in 0..200 {
+                        let n =
+                            tokenizer_encode_handle(handle, text.as_ptr(), ids.as_mut_ptr(), 16);
+                        assert_eq!(n, 11);
+                    }
+                })
+            })
+            .collect();
+        for _ in 0..5 {
+            assert_eq!(tokenizer_reload(handle, c(FIXTURE).as_ptr()), 0);
+        }
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert!(tokenizer_generation() >= before + 5);
+
+        assert_eq!(
+            tokenizer_reload(handle, c("/definitely/missing/tokenizer.json").as_ptr()),
+            ErrorCode::FileNotFound as i32
+        );
+        let mut ids = [0i32; 16];
+        let text = c("hello");
+        assert!(tokenizer_encode_handle(handle, text.as_ptr(), ids.as_mut_ptr(), 16) > 0);
+        tokenizer_free(handle);
+        assert_eq!(
+            tokenizer_reload(handle, c(FIXTURE).as_ptr()),
+            ErrorCode::NotInitialized as i32
+        );
+    }
+}
@@ -0,0 +1,320 @@
+use super::{guarded, hnsw, in_slice, out_ref, out_slice, status, str_arg, tokenizer};
+use crate::bm25::{self, Analyzer, Bm25};
+use crate::error::{self, Error, ErrorCode};
+use crate::fusion::{self, Fused};
+use crate::registry::{Handle, Registry, INVALID_HANDLE};
+use std::os::raw::c_char;
+use std::sync::{Arc, PoisonError, RwLock};
+
+// Candidates taken from each list before fusing, so a message ranked just outside the
+// requested `k` by one search can still be lifted by the other.
+const FUSION_DEPTH: usize = 50;
+
+static INDEXES: Registry<RwLock<Bm25>> = Registry::new();
+
+/// Drops every handle; see `tokenizer_cleanup`.
+pub(super) fn clear() {
+    INDEXES.clear();
+}
+
+fn index(handle: Handle) -> Result<Arc<RwLock<Bm25>>, Error> {
+    INDEXES.get(handle).ok_or_else(|| {
+        Error::new(
+            ErrorCode::NotInitialized,
+            format!("unknown BM25 index handle {handle}"),
+        )
+    })
+}
+
+fn or_default(value: f32, default: f32) -> f32 {
+    if value.is_nan() {
+        default
+    } else {
+        value
+    }
+}
+
+fn write_fused(
+    fused: &[Fused],
+    out_ids: *mut i64,
+    out_scores: *mut f32,
+    out_sources: *mut u32,
+) -> Result<i32, Error> {
+    let ids = out_slice(out_ids, fused.len(), "out_ids")?;
+    let scores = out_slice(out_scores, fused.len(), "out_scores")?;
+    let sources = out_slice(out_sources, fused.len(), "out_sources")?;
+    for (i, result) in fused.iter().enumerate() {
+        ids[i] = result.id;
+        scores[i] = result.score;
+        sources[i] = result.source;
+    }
+    Ok(fused.len() as i32)
+}
+
+/// Creates an empty BM25 index. Terms come from the tokenizer `tokenizer_handle`, or from a
+/// Unicode word splitter when it is `0`. `k1` and `b` may be NaN for the defaults.
+/// Returns `0` on failure.
+#[no_mangle]
+pub extern "C" fn tokenizer_bm25_create(tokenizer_handle: Handle, k1: f32, b: f32) -> Handle {
+    guarded(|| {
+        let analyzer = if tokenizer_handle == INVALID_HANDLE {
+            Analyzer::Words
+        } else {
+            Analyzer::Tokenizer(tokenizer(tokenizer_handle)?)
+        };
+        let k1 = or_default(k1, bm25::DEFAULT_K1);
+        let b = or_default(b, bm25::DEFAULT_B);
+        if k1 < 0.0 || !(0.0..=1.0).contains(&b) {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("k1 must not be negative and b must be in [0, 1], got {k1} and {b}"),
+            ));
+        }
+        Ok(INDEXES.insert(RwLock::new(Bm25::new(analyzer, k1, b))))
+    })
+    .unwrap_or_else(|e| {
+        error::report(e);
+        INVALID_HANDLE
+    })
+}
+
+/// Indexes a message's text, replacing it if the message was already indexed.
+#[no_mangle]
+pub extern "C" fn tokenizer_bm25_upsert(handle: Handle, id: i64, text: *const c_char) -> i32 {
+    status(|| {
+        let text = str_arg(text, "text")?;
+        let index = index(handle)?;
+        index
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .upsert(id, text)?;
+        Ok(0)
+    })
+}
+
+/// Returns `1` if the message was removed and `0` if it was not indexed.
+#[no_mangle]
+pub extern "C" fn tokenizer_bm25_remove(handle: Handle, id: i64) -> i32 {
+    status(|| {
+        let index = index(handle)?;
+        let removed = index
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(id);
+        Ok(removed as i32)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_bm25_count(handle: Handle, out_count: *mut usize) -> i32 {
+    status(|| {
+        let out_count = out_ref(out_count, "out_count")?;
+        *out_count = index(handle)?
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len();
+        Ok(0)
+    })
+}
+
+/// Writes up to `k` message ids and BM25 scores, best first, and returns how many were written.
+#[no_mangle]
+pub extern "C" fn tokenizer_bm25_search(
+    handle: Handle,
+    query: *const c_char,
+    k: usize,
+    out_ids: *mut i64,
+    out_scores: *mut f32,
+) -> i32 {
+    status(|| {
+        let query = str_arg(query, "query")?;
+        let found = index(handle)?
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .search(query, k)?;
+        let ids = out_slice(out_ids, found.len(), "out_ids")?;
+        let scores = out_slice(out_scores, found.len(), "out_scores")?;
+        for (i, (id, score)) in found.iter().enumerate() {
+            ids[i] = *id;
+            scores[i] = *score;
+        }
+        Ok(found.len() as i32)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_bm25_free(handle: Handle) -> i32 {
+    status(|| {
+        if INDEXES.remove(handle) {
+            Ok(0)
+        } else {
+            Err(Error::new(
+                ErrorCode::NotInitialized,
+                format!("unknown BM25 index handle {handle}"),
+            ))
+        }
+    })
+}
+
+/// Fuses two ranked lists of message ids, best first, with reciprocal rank fusion and writes
+/// up to `limit` ids, fused scores and `SOURCE_*` flags. `rrf_k` may be NaN for the default
+/// of 60. Returns the number of results written.
+#[no_mangle]
+pub extern "C" fn tokenizer_rank_fusion(
+    lexical_ids: *const i64,
+    lexical_count: usize,
+    semantic_ids: *const i64,
+    semantic_count: usize,
+    rrf_k: f32,
+    limit: usize,
+    out_ids: *mut i64,
+    out_scores: *mut f32,
+    out_sources: *mut u32,
+) -> i32 {
+    status(|| {
+        let lexical = in_slice(lexical_ids, lexical_count, "lexical_ids")?;
+        let semantic = in_slice(semantic_ids, semantic_count, "semantic_ids")?;
+        let rrf_k = or_default(rrf_k, fusion::DEFAULT_RRF_K);
+        let fused = fusion::reciprocal_rank_fusion(lexical, semantic, rrf_k, limit);
+        write_fused(&fused, out_ids, out_scores, out_sources)
+    })
+}
+
+/// Searches a BM25 index with `query` and an HNSW index with `query_vector`, then fuses both
+/// rankings as `tokenizer_rank_fusion` does. Returns the number of results written.
+#[no_mangle]
+pub extern "C" fn tokenizer_hybrid_search(
+    bm25_handle: Handle,
+    hnsw_handle: Handle,
+    query: *const c_char,
+    query_vector: *const f32,
+    dim: usize,
+    k: usize,
+    out_ids: *mut i64,
+    out_scores: *mut f32,
+    out_sources: *mut u32,
+) -> i32 {
+    status(|| {
+        let query = str_arg(query, "query")?;
+        let query_vector = in_slice(query_vector, dim, "query_vector")?;
+        let depth = k.max(FUSION_DEPTH);
+        let lexical = index(bm25_handle)?
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .search(query, depth)?;
+        let semantic = hnsw::index(hnsw_handle)?
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .search(
+                query_vector,
+                depth,
+                depth.max(crate::hnsw::DEFAULT_EF_SEARCH),
+                None,
+            )?;
+        let ids = |found: Vec<(i64, f32)>| found.into_iter().map(|f| f.0).collect::<Vec<_>>();
+        let fused =
+            fusion::reciprocal_rank_fusion(&ids(lexical), &ids(semantic), fusion::DEFAULT_RRF_K, k);
+        write_fused(&fused, out_ids, out_scores, out_sources)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fusion::{SOURCE_LEXICAL, SOURCE_SEMANTIC};
+    use crate::similarity::METRIC_COSINE;
+    use std::ffi::CString;
+
+    #[test]
+    fn hybrid_search_fuses_keyword_and_vector_hits() {
+        let bm25 = tokenizer_bm25_create(INVALID_HANDLE, f32::NAN, f32::NAN);
+        let hnsw = super::hnsw::tokenizer_hnsw_create(2, METRIC_COSINE, 0, 0);
+        let messages = [
+            (10, "the build fails with ERR_SSL_PROTOCOL", [0.0f32, 1.0]),
+            (11, "how do I fix certificate errors", [1.0, 0.1]),
+            (12, "lunch plans", [-1.0, 0.0]),
+        ];
+        for (id, text, vector) in messages {
+            let text = CString::new(text).unwrap();
+            assert_eq!(tokenizer_bm25_upsert(bm25, id, text.as_ptr()), 0);
+            assert_eq!(
+                super::hnsw::tokenizer_hnsw_add(hnsw, id, vector.as_ptr(), 2),
+                0
+            );
+        }
+        let mut count = 0;
+        assert_eq!(tokenizer_bm25_count(bm25, &mut count), 0);
+        assert_eq!(count, 3);
+
+        let query = CString::new("err_ssl_protocol").unwrap();
+        let mut ids = [0i64; 3];
+        let mut scores = [0f32; 3];
+        let mut sources = [0u32; 3];
+        assert_eq!(
+            tokenizer_bm25_search(
+                bm25,
+                query.as_ptr(),
+                3,
+                ids.as_mut_ptr(),
+                scores.as_mut_ptr()
+            ),
+            1
+        );
+        assert_eq!(ids[0], 10);
+
+        let written = tokenizer_hybrid_search(
+            bm25,
+            hnsw,
+            query.as_ptr(),
+            [1.0f32, 0.0].as_ptr(),
+            2,
+            2,
+            ids.as_mut_ptr(),
+            scores.as_mut_ptr(),
+            sources.as_mut_ptr(),
+        );
+        assert_eq!(written, 2);
+        assert_eq!(ids[..2], [10, 11]);
+        assert_eq!(
+            sources[..2],
+            [SOURCE_LEXICAL | SOURCE_SEMANTIC, SOURCE_SEMANTIC]
+        );
+
+        assert_eq!(tokenizer_bm25_remove(bm25, 10), 1);
+        assert_eq!(tokenizer_bm25_free(bm25), 0);
+        assert_eq!(tokenizer_bm25_free(bm25), ErrorCode::NotInitialized as i32);
+        super::hnsw::tokenizer_hnsw_free(hnsw);
+    }
+
+    #[test]
+    fn tokenizer_analyzer_indexes_past_a_configured_truncation() {
+        let path = CString::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/tokenizer_configured.json"
+        ))
+        .unwrap();
+        let tokenizer = crate::ffi::tokenizer_create(path.as_ptr());
+        let bm25 = tokenizer_bm25_create(tokenizer, f32::NAN, f32::NAN);
+        for (id, text) in [
+            (1, "the quick brown fox jumps over the lazy dog"),
+            (2, "hello world"),
+        ] {
+            let text = CString::new(text).unwrap();
+            assert_eq!(tokenizer_bm25_upsert(bm25, id, text.as_ptr()), 0);
+        }
+        let query = CString::new("lazy dog").unwrap();
+        let mut ids = [0i64; 2];
+        let mut scores = [0f32; 2];
+        let found = tokenizer_bm25_search(
+            bm25,
+            query.as_ptr(),
+            2,
+            ids.as_mut_ptr(),
+            scores.as_mut_ptr(),
+        );
+        assert_eq!(found, 1);
+        assert_eq!(ids[0], 1);
+        tokenizer_bm25_free(bm25);
+        crate::ffi::tokenizer_free(tokenizer);
+    }
+}
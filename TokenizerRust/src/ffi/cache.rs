@@ -0,0 +1,83 @@
+use super::{guarded, out_ref, status, str_arg};
+use crate::cache::{self, CacheStats, EmbeddingCache};
+use crate::error::{self, Error, ErrorCode};
+use crate::registry::{Handle, Registry, INVALID_HANDLE};
+use std::os::raw::c_char;
+use std::sync::Arc;
+
+static CACHES: Registry<EmbeddingCache> = Registry::new();
+
+/// Drops every handle; see `tokenizer_cleanup`.
+pub(super) fn clear() {
+    CACHES.clear();
+}
+
+pub(super) fn cache(handle: Handle) -> Result<Arc<EmbeddingCache>, Error> {
+    CACHES.get(handle).ok_or_else(|| {
+        Error::new(
+            ErrorCode::NotInitialized,
+            format!("unknown embedding cache handle {handle}"),
+        )
+    })
+}
+
+/// Creates an embedding cache holding up to `max_bytes` in memory (`0` for 64 MiB). When
+/// `path` is not null, embeddings are also appended to that file and read back from it after
+/// a restart. Returns `0` on failure.
+#[no_mangle]
+pub extern "C" fn tokenizer_embedding_cache_create(
+    max_bytes: usize,
+    path: *const c_char,
+) -> Handle {
+    guarded(|| {
+        let max_bytes = if max_bytes == 0 {
+            cache::DEFAULT_MAX_BYTES
+        } else {
+            max_bytes
+        };
+        let cache = if path.is_null() {
+            EmbeddingCache::new(max_bytes)
+        } else {
+            EmbeddingCache::with_file(max_bytes, str_arg(path, "path")?)?
+        };
+        Ok(CACHES.insert(cache))
+    })
+    .unwrap_or_else(|e| {
+        error::report(e);
+        INVALID_HANDLE
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_embedding_cache_stats(
+    handle: Handle,
+    out_stats: *mut CacheStats,
+) -> i32 {
+    status(|| {
+        *out_ref(out_stats, "out_stats")? = cache(handle)?.stats();
+        Ok(0)
+    })
+}
+
+/// Empties the memory tier and resets the counters; the disk file is kept.
+#[no_mangle]
+pub extern "C" fn tokenizer_embedding_cache_clear(handle: Handle) -> i32 {
+    status(|| {
+        cache(handle)?.clear();
+        Ok(0)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_embedding_cache_free(handle: Handle) -> i32 {
+    status(|| {
+        if CACHES.remove(handle) {
+            Ok(0)
+        } else {
+            Err(Error::new(
+                ErrorCode::NotInitialized,
+                format!("unknown embedding cache handle {handle}"),
+            ))
+        }
+    })
+}
@@ -0,0 +1,191 @@
+use super::{guarded, out_ref, out_slice, status, str_arg, tokenizer, write_utf8};
+use crate::chat_template::ChatTemplate;
+use crate::encoding;
+use crate::error::{self, Error, ErrorCode};
+use crate::registry::{Handle, Registry, INVALID_HANDLE};
+use std::os::raw::c_char;
+use std::sync::Arc;
+
+static TEMPLATES: Registry<ChatTemplate> = Registry::new();
+
+/// Drops every handle; see `tokenizer_cleanup`.
+pub(super) fn clear() {
+    TEMPLATES.clear();
+}
+
+pub(super) fn template(handle: Handle) -> Result<Arc<ChatTemplate>, Error> {
+    TEMPLATES.get(handle).ok_or_else(|| {
+        Error::new(
+            ErrorCode::NotInitialized,
+            format!("unknown chat template handle {handle}"),
+        )
+    })
+}
+
+fn render(
+    handle: Handle,
+    messages_json: *const c_char,
+    add_generation_prompt: bool,
+) -> Result<String, Error> {
+    let messages = serde_json::from_str(str_arg(messages_json, "messages_json")?)
+        .map_err(|e| Error::new(ErrorCode::JsonParse, format!("invalid messages: {e}")))?;
+    template(handle)?.render(&messages, add_generation_prompt)
+}
+
+/// Loads the `chat_template` from a `tokenizer_config.json`. Returns `0` on failure.
+#[no_mangle]
+pub extern "C" fn tokenizer_chat_template_create(config_path: *const c_char) -> Handle {
+    guarded(|| {
+        let template = ChatTemplate::from_file(str_arg(config_path, "config_path")?)?;
+        Ok(TEMPLATES.insert(template))
+    })
+    .unwrap_or_else(|e| {
+        error::report(e);
+        INVALID_HANDLE
+    })
+}
+
+/// Renders a JSON array of `{"role", "content"}` messages into the UTF-8 prompt,
+/// returning its length in bytes.
+#[no_mangle]
+pub extern "C" fn tokenizer_chat_template_render(
+    template_handle: Handle,
+    messages_json: *const c_char,
+    add_generation_prompt: bool,
+    out_buf: *mut u8,
+    buf_len: usize,
+    out_required: *mut usize,
+) -> i32 {
+    status(|| {
+        let out_required = out_ref(out_required, "out_required")?;
+        let prompt = render(template_handle, messages_json, add_generation_prompt)?;
+        write_utf8(&prompt, out_buf, buf_len, out_required)
+    })
+}
+
+/// Renders the prompt and encodes it with the given tokenizer, returning the number of ids.
+/// The template already contains the special tokens, so none are added during encoding.
+#[no_mangle]
+pub extern "C" fn tokenizer_chat_template_encode(
+    template_handle: Handle,
+    tokenizer_handle: Handle,
+    messages_json: *const c_char,
+    add_generation_prompt: bool,
+    out_ids: *mut i32,
+    max_len: usize,
+    out_required: *mut usize,
+) -> i32 {
+    status(|| {
+        let out_required = out_ref(out_required, "out_required")?;
+        let tokenizer = tokenizer(tokenizer_handle)?;
+        let prompt = render(template_handle, messages_json, add_generation_prompt)?;
+        let encoding = encoding::encode(&tokenizer, &prompt, false, None)?;
+        let ids = encoding.get_ids();
+        *out_required = ids.len();
+        if ids.len() > max_len {
+            return Err(Error::new(
+                ErrorCode::BufferTooSmall,
+                format!("{} ids do not fit in max_len {max_len}", ids.len()),
+            ));
+        }
+        for (out, &id) in out_slice(out_ids, ids.len(), "out_ids")?
+            .iter_mut()
+            .zip(ids)
+        {
+            *out = id as i32;
+        }
+        Ok(ids.len() as i32)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_chat_template_free(template_handle: Handle) -> i32 {
+    status(|| {
+        if TEMPLATES.remove(template_handle) {
+            Ok(0)
+        } else {
+            Err(Error::new(
+                ErrorCode::NotInitialized,
+                format!("unknown chat template handle {template_handle}"),
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::{tokenizer_create, tokenizer_free};
+    use std::ffi::CString;
+
+    fn fixture(name: &str) -> CString {
+        CString::new(format!("{}/testdata/{name}", env!("CARGO_MANIFEST_DIR"))).unwrap()
+    }
+
+    #[test]
+    fn renders_and_encodes_messages() {
+        let template = tokenizer_chat_template_create(fixture("tokenizer_config.json").as_ptr());
+        assert_ne!(template, INVALID_HANDLE);
+        let tokenizer_handle = tokenizer_create(fixture("byte_fallback.json").as_ptr());
+        let messages = CString::new(r#"[{"role":"user","content":"Hello world"}]"#).unwrap();
+
+        let mut buf = [0u8; 128];
+        let mut required = 0;
+        let len = tokenizer_chat_template_render(
+            template,
+            messages.as_ptr(),
+            true,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut required,
+        );
+        let expected =
+            "<|im_start|>user<|im_sep|>Hello world<|im_end|><|im_start|>assistant<|im_sep|>";
+        assert_eq!(len as usize, expected.len());
+        assert_eq!(&buf[..len as usize], expected.as_bytes());
+
+        let mut ids = [0i32; 64];
+        let count = tokenizer_chat_template_encode(
+            template,
+            tokenizer_handle,
+            messages.as_ptr(),
+            true,
+            ids.as_mut_ptr(),
+            ids.len(),
+            &mut required,
+        );
+        assert!(count > 0);
+        let tokenizer = crate::registry::get(tokenizer_handle).unwrap();
+        let im_start = tokenizer.token_to_id("<|im_start|>").unwrap() as i32;
+        assert_eq!(ids[0], im_start);
+        assert_eq!(
+            tokenizer_chat_template_encode(
+                template,
+                tokenizer_handle,
+                messages.as_ptr(),
+                true,
+                ids.as_mut_ptr(),
+                2,
+                &mut required,
+            ),
+            ErrorCode::BufferTooSmall as i32
+        );
+        assert_eq!(required, count as usize);
+
+        let not_array = CString::new(r#"{"role":"user"}"#).unwrap();
+        assert_eq!(
+            tokenizer_chat_template_render(
+                template,
+                not_array.as_ptr(),
+                true,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut required,
+            ),
+            ErrorCode::InvalidArgument as i32
+        );
+
+        assert_eq!(tokenizer_chat_template_free(template), 0);
+        tokenizer_free(tokenizer_handle);
+    }
+}
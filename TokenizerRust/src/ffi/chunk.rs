@@ -0,0 +1,137 @@
+use super::{
+    buffer_len, check_offset_kind, opt_out_slice, out_ref, out_slice, status, str_arg, tokenizer,
+    OFFSETS_UTF16,
+};
+use crate::chunk;
+use crate::encoding;
+use crate::error::{Error, ErrorCode};
+use crate::registry::Handle;
+use std::os::raw::c_char;
+
+/// Splits `text` into chunks of at most `max_tokens` tokens overlapping by about `overlap`,
+/// preferring Markdown sections, paragraphs and sentences as chunk ends. `out_spans` receives
+/// `(start, end)` pairs in bytes or UTF-16 code units depending on `offset_kind`, and
+/// `out_token_counts`, which may be null, the tokens of each chunk. `out_chunk_count` receives
+/// the number of chunks even when `BufferTooSmall` is returned. Returns the number of chunks.
+#[no_mangle]
+pub extern "C" fn tokenizer_chunk_text(
+    handle: Handle,
+    text: *const c_char,
+    add_special_tokens: bool,
+    max_tokens: usize,
+    overlap: usize,
+    offset_kind: u32,
+    out_spans: *mut u32,
+    out_token_counts: *mut usize,
+    max_chunks: usize,
+    out_chunk_count: *mut usize,
+) -> i32 {
+    status(|| {
+        let text = str_arg(text, "text")?;
+        check_offset_kind(offset_kind)?;
+        let out_chunk_count = out_ref(out_chunk_count, "out_chunk_count")?;
+        let tokenizer = tokenizer(handle)?;
+        let chunks = chunk::chunk(&tokenizer, text, add_special_tokens, max_tokens, overlap)?;
+        *out_chunk_count = chunks.len();
+        if chunks.len() > max_chunks {
+            return Err(Error::new(
+                ErrorCode::BufferTooSmall,
+                format!(
+                    "{} chunks do not fit in max_chunks {max_chunks}",
+                    chunks.len()
+                ),
+            ));
+        }
+
+        let mut spans: Vec<(usize, usize)> = chunks.iter().map(|c| (c.start, c.end)).collect();
+        if offset_kind == OFFSETS_UTF16 {
+            spans = encoding::utf16_offsets(text, &spans);
+        }
+        let out = out_slice(out_spans, buffer_len(chunks.len(), 2)?, "out_spans")?;
+        for (dst, &(start, end)) in out.chunks_exact_mut(2).zip(&spans) {
+            dst[0] = start as u32;
+            dst[1] = end as u32;
+        }
+        if let Some(out) = opt_out_slice(out_token_counts, chunks.len()) {
+            for (dst, chunk) in out.iter_mut().zip(&chunks) {
+                *dst = chunk.tokens;
+            }
+        }
+        Ok(chunks.len() as i32)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::{tokenizer_create, tokenizer_free, OFFSETS_BYTES};
+    use std::ffi::CString;
+    use std::ptr;
+
+    #[test]
+    fn writes_utf16_spans_and_reports_chunk_count() {
+        let path = CString::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/tokenizer.json"
+        ))
+        .unwrap();
+        let handle = tokenizer_create(path.as_ptr());
+        let text = "zażółć gęślą jaźń, the test. the quick brown fox jumps over the lazy dog.";
+        let c_text = CString::new(text).unwrap();
+        let mut spans = [0u32; 8];
+        let mut counts = [0usize; 4];
+        let mut count = 0;
+        let chunk = |spans: &mut [u32], counts: *mut usize, max_chunks, count: &mut usize| {
+            tokenizer_chunk_text(
+                handle,
+                c_text.as_ptr(),
+                true,
+                12,
+                0,
+                OFFSETS_UTF16,
+                spans.as_mut_ptr(),
+                counts,
+                max_chunks,
+                count,
+            )
+        };
+        assert_eq!(chunk(&mut spans, counts.as_mut_ptr(), 4, &mut count), 2);
+        assert_eq!(spans[..4], [0, 28, 29, 73]);
+        assert_eq!(counts[..2], [9, 12]);
+        assert_eq!(
+            chunk(&mut spans, ptr::null_mut(), 1, &mut count),
+            ErrorCode::BufferTooSmall as i32
+        );
+        assert_eq!(count, 2);
+        tokenizer_free(handle);
+    }
+
+    #[test]
+    fn chunks_text_past_a_configured_truncation() {
+        let spans = |name: &str| {
+            let path =
+                CString::new(format!("{}/testdata/{name}", env!("CARGO_MANIFEST_DIR"))).unwrap();
+            let handle = tokenizer_create(path.as_ptr());
+            let text = CString::new("the quick brown fox. jumps over the lazy dog.").unwrap();
+            let mut spans = [0u32; 16];
+            let mut count = 0;
+            let written = tokenizer_chunk_text(
+                handle,
+                text.as_ptr(),
+                false,
+                6,
+                0,
+                OFFSETS_BYTES,
+                spans.as_mut_ptr(),
+                ptr::null_mut(),
+                8,
+                &mut count,
+            );
+            tokenizer_free(handle);
+            spans[..2 * written as usize].to_vec()
+        };
+        let plain = spans("tokenizer.json");
+        assert_eq!(plain.last(), Some(&45));
+        assert_eq!(spans("tokenizer_configured.json"), plain);
+    }
+}
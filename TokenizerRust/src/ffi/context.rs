@@ -0,0 +1,170 @@
+use super::chat::template;
+use super::{out_ref, status, str_arg, tokenizer};
+use crate::context::{self, TokenCounter};
+use crate::error::{Error, ErrorCode};
+use crate::registry::{Handle, INVALID_HANDLE};
+use std::os::raw::c_char;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PackResult {
+    /// Index of the oldest history message to send; older ones are dropped.
+    pub first_kept: usize,
+    /// Whether the latest user message must be cut to `kept_bytes`/`kept_utf16`.
+    pub truncated: bool,
+    pub kept_bytes: usize,
+    pub kept_utf16: usize,
+    pub token_count: usize,
+}
+
+/// Decides which history messages fit in `budget` tokens next to the system prompt.
+/// `history_json` is a JSON array of `{"role", "content"}` messages, oldest first.
+/// Pass `0` as `template_handle` to count message contents without a chat template,
+/// and null as `system_prompt` when there is none.
+#[no_mangle]
+pub extern "C" fn tokenizer_pack_context(
+    tokenizer_handle: Handle,
+    template_handle: Handle,
+    system_prompt: *const c_char,
+    history_json: *const c_char,
+    budget: usize,
+    add_generation_prompt: bool,
+    out_result: *mut PackResult,
+) -> i32 {
+    status(|| {
+        let out_result = out_ref(out_result, "out_result")?;
+        let tokenizer = tokenizer(tokenizer_handle)?;
+        let template = match template_handle {
+            INVALID_HANDLE => None,
+            handle => Some(template(handle)?),
+        };
+        let system_prompt = if system_prompt.is_null() {
+            None
+        } else {
+            Some(str_arg(system_prompt, "system_prompt")?)
+        };
+        let history: Vec<serde_json::Value> =
+            serde_json::from_str(str_arg(history_json, "history_json")?)
+                .map_err(|e| Error::new(ErrorCode::JsonParse, format!("invalid history: {e}")))?;
+        let counter = TokenCounter {
+            tokenizer: &tokenizer,
+            template: template.as_deref(),
+            add_generation_prompt,
+        };
+        let packing = counter.pack(system_prompt, &history, budget)?;
+        let latest = context::latest_turn(&history)
+            .and_then(|i| history[i]["content"].as_str())
+            .unwrap_or_default();
+        let kept = packing.truncated_to.map_or(latest, |end| &latest[..end]);
+        *out_result = PackResult {
+            first_kept: packing.first_kept,
+            truncated: packing.truncated_to.is_some(),
+            kept_bytes: kept.len(),
+            kept_utf16: kept.encode_utf16().count(),
+            token_count: packing.token_count,
+        };
+        Ok(0)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::chat::{tokenizer_chat_template_create, tokenizer_chat_template_free};
+    use crate::ffi::{tokenizer_create, tokenizer_free};
+    use std::ffi::CString;
+
+    fn fixture(name: &str) -> CString {
+        CString::new(format!("{}/testdata/{name}", env!("CARGO_MANIFEST_DIR"))).unwrap()
+    }
+
+    #[test]
+    fn reports_kept_messages_and_truncation_in_utf16() {
+        let tokenizer_handle = tokenizer_create(fixture("byte_fallback.json").as_ptr());
+        let template = tokenizer_chat_template_create(fixture("tokenizer_config.json").as_ptr());
+        let history = CString::new(
+            r#"[{"role":"user","content":"Hello"},{"role":"user","content":"world żółw Hello"}]"#,
+        )
+        .unwrap();
+        let mut result = PackResult::default();
+        assert_eq!(
+            tokenizer_pack_context(
+                tokenizer_handle,
+                template,
+                std::ptr::null(),
+                history.as_ptr(),
+                4096,
+                true,
+                &mut result,
+            ),
+            0
+        );
+        assert_eq!(result.first_kept, 0);
+        assert!(!result.truncated);
+        assert_eq!(result.kept_utf16, "world żółw Hello".encode_utf16().count());
+
+        let mut small = PackResult::default();
+        tokenizer_pack_context(
+            tokenizer_handle,
+            INVALID_HANDLE,
+            std::ptr::null(),
+            history.as_ptr(),
+            2,
+            false,
+            &mut small,
+        );
+        assert_eq!(small.first_kept, 1);
+        assert!(small.truncated);
+        assert_eq!((small.kept_bytes, small.kept_utf16), (6, 6));
+
+        assert_eq!(
+            tokenizer_pack_context(
+                tokenizer_handle,
+                template,
+                std::ptr::null(),
+                history.as_ptr(),
+                0,
+                true,
+                &mut small,
+            ),
+            ErrorCode::BufferTooSmall as i32
+        );
+        tokenizer_chat_template_free(template);
+        tokenizer_free(tokenizer_handle);
+    }
+
+    #[test]
+    fn packs_against_real_counts_despite_configured_truncation() {
+        let history = CString::new(
+            r#"[{"role":"user","content":"hello world"},{"role":"user","content":"the quick brown fox jumps over the lazy dog"}]"#,
+        )
+        .unwrap();
+        let pack = |name| {
+            let handle = tokenizer_create(fixture(name).as_ptr());
+            let mut result = PackResult::default();
+            assert_eq!(
+                tokenizer_pack_context(
+                    handle,
+                    INVALID_HANDLE,
+                    std::ptr::null(),
+                    history.as_ptr(),
+                    8,
+                    false,
+                    &mut result,
+                ),
+                0
+            );
+            tokenizer_free(handle);
+            result
+        };
+        let configured = pack("tokenizer_configured.json");
+        assert_eq!(configured.first_kept, 1);
+        assert!(configured.truncated);
+        assert!(configured.token_count <= 8);
+        let plain = pack("tokenizer.json");
+        assert_eq!(
+            (configured.kept_bytes, configured.token_count),
+            (plain.kept_bytes, plain.token_count)
+        );
+    }
+}
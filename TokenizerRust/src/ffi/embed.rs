@@ -0,0 +1,454 @@
+use super::cache::cache;
+use super::{
+    buffer_len, guarded, in_slice, null_error, out_ref, out_slice, status, str_arg, tokenizer,
+    write_utf8,
+};
+use crate::embed::{Embedder, EmbedderOptions};
+use crate::error::{self, Error, ErrorCode};
+use crate::pooling::{self, Pooling};
+use crate::profile::{Profile, TextKind, TEXT_RAW};
+use crate::registry::{Handle, Registry, INVALID_HANDLE};
+use std::os::raw::c_char;
+use std::sync::Arc;
+
+static EMBEDDERS: Registry<Embedder> = Registry::new();
+
+/// Drops every handle; see `tokenizer_cleanup`.
+pub(super) fn clear() {
+    EMBEDDERS.clear();
+}
+
+fn embedder(handle: Handle) -> Result<Arc<Embedder>, Error> {
+    EMBEDDERS.get(handle).ok_or_else(|| {
+        Error::new(
+            ErrorCode::NotInitialized,
+            format!("unknown embedder handle {handle}"),
+        )
+    })
+}
+
+fn report_handle(result: Result<Handle, Error>) -> Handle {
+    result.unwrap_or_else(|e| {
+        error::report(e);
+        INVALID_HANDLE
+    })
+}
+
+fn create(
+    model_path: *const c_char,
+    tokenizer_handle: Handle,
+    options: &EmbedderOptions,
+) -> Handle {
+    report_handle(guarded(|| {
+        let path = str_arg(model_path, "model_path")?;
+        let tokenizer = tokenizer(tokenizer_handle)?;
+        Ok(EMBEDDERS.insert(Embedder::load(path, tokenizer, options)?))
+    }))
+}
+
+/// Loads an ONNX encoder that embeds text tokenized by `tokenizer_handle`, truncated to
+/// `max_length` tokens (`0` for 512), with mean pooling. Returns `0` on failure.
+#[no_mangle]
+pub extern "C" fn tokenizer_embedder_create(
+    model_path: *const c_char,
+    tokenizer_handle: Handle,
+    max_length: usize,
+) -> Handle {
+    let options = EmbedderOptions {
+        max_length,
+        ..Default::default()
+    };
+    create(model_path, tokenizer_handle, &options)
+}
+
+/// Like `tokenizer_embedder_create`, with the pooling strategy and Matryoshka truncation
+/// taken from `options`. Returns `0` on failure.
+#[no_mangle]
+pub extern "C" fn tokenizer_embedder_create_ex(
+    model_path: *const c_char,
+    tokenizer_handle: Handle,
+    options: *const EmbedderOptions,
+) -> Handle {
+    match unsafe { options.as_ref() } {
+        Some(options) => create(model_path, tokenizer_handle, options),
+        None => {
+            error::report(null_error("options"));
+            INVALID_HANDLE
+        }
+    }
+}
+
+/// Loads an ONNX encoder configured by the `embedding_profile.json` manifest at `profile_path`,
+/// or next to the model when it is null. The manifest sets the query and document templates,
+/// maximum length, pooling and normalization. Returns `0` on failure.
+#[no_mangle]
+pub extern "C" fn tokenizer_embedder_create_with_profile(
+    model_path: *const c_char,
+    tokenizer_handle: Handle,
+    profile_path: *const c_char,
+) -> Handle {
+    report_handle(guarded(|| {
+        let path = str_arg(model_path, "model_path")?;
+        let profile = if profile_path.is_null() {
+            Profile::next_to(path)?
+        } else {
+            Profile::from_file(str_arg(profile_path, "profile_path")?)?
+        };
+        let tokenizer = tokenizer(tokenizer_handle)?;
+        Ok(EMBEDDERS.insert(Embedder::with_profile(path, tokenizer, profile)?))
+    }))
+}
+
+/// Writes the profile's version string, to be stored with the embeddings; empty when the
+/// embedder was created without a profile.
+#[no_mangle]
+pub extern "C" fn tokenizer_embedder_version(
+    handle: Handle,
+    out_buf: *mut u8,
+    buf_len: usize,
+    out_required: *mut usize,
+) -> i32 {
+    status(|| {
+        let out_required = out_ref(out_required, "out_required")?;
+        let embedder = embedder(handle)?;
+        let version = embedder.profile().map_or("", |p| p.version.as_str());
+        write_utf8(version, out_buf, buf_len, out_required)
+    })
+}
+
+/// Writes `text` with the profile's template for `kind` (`TEXT_QUERY` or `TEXT_DOCUMENT`)
+/// applied, exactly as `tokenizer_embed_ex` would embed it.
+#[no_mangle]
+pub extern "C" fn tokenizer_embedder_prepare_text(
+    handle: Handle,
+    kind: u32,
+    text: *const c_char,
+    out_buf: *mut u8,
+    buf_len: usize,
+    out_required: *mut usize,
+) -> i32 {
+    status(|| {
+        let kind = TextKind::from_raw(kind)?;
+        let text = str_arg(text, "text")?;
+        let out_required = out_ref(out_required, "out_required")?;
+        let embedder = embedder(handle)?;
+        let prepared = match embedder.profile() {
+            Some(profile) => profile.prepare(kind, text),
+            None => text.into(),
+        };
+        write_utf8(&prepared, out_buf, buf_len, out_required)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_embedder_dim(handle: Handle, out_dim: *mut usize) -> i32 {
+    status(|| {
+        *out_ref(out_dim, "out_dim")? = embedder(handle)?.dim();
+        Ok(0)
+    })
+}
+
+/// Embeds `count` texts into `out_vectors`, a row-major `[count, dim]` buffer of `capacity`
+/// floats. Returns the number of vectors written.
+#[no_mangle]
+pub extern "C" fn tokenizer_embed(
+    handle: Handle,
+    texts: *const *const c_char,
+    count: usize,
+    out_vectors: *mut f32,
+    capacity: usize,
+) -> i32 {
+    tokenizer_embed_ex(handle, texts, count, TEXT_RAW, out_vectors, capacity)
+}
+
+/// Like `tokenizer_embed`, embedding the texts as queries or documents (`TEXT_QUERY` or
+/// `TEXT_DOCUMENT`) with the profile's templates; `TEXT_RAW` embeds them as given.
+#[no_mangle]
+pub extern "C" fn tokenizer_embed_ex(
+    handle: Handle,
+    texts: *const *const c_char,
+    count: usize,
+    kind: u32,
+    out_vectors: *mut f32,
+    capacity: usize,
+) -> i32 {
+    tokenizer_embed_cached(
+        handle,
+        INVALID_HANDLE,
+        texts,
+        count,
+        kind,
+        out_vectors,
+        capacity,
+    )
+}
+
+/// Like `tokenizer_embed_ex`, serving texts already in the cache `cache_handle` without
+/// tokenizing them and caching the rest. A `cache_handle` of `0` disables caching.
+#[no_mangle]
+pub extern "C" fn tokenizer_embed_cached(
+    handle: Handle,
+    cache_handle: Handle,
+    texts: *const *const c_char,
+    count: usize,
+    kind: u32,
+    out_vectors: *mut f32,
+    capacity: usize,
+) -> i32 {
+    status(|| {
+        let kind = TextKind::from_raw(kind)?;
+        let embedder = embedder(handle)?;
+        let required = buffer_len(count, embedder.dim())?;
+        if capacity < required {
+            return Err(Error::new(
+                ErrorCode::BufferTooSmall,
+                format!("{required} floats do not fit in capacity {capacity}"),
+            ));
+        }
+        let texts = in_slice(texts, count, "texts")?
+            .iter()
+            .map(|&text| str_arg(text, "text"))
+            .collect::<Result<Vec<_>, _>>()?;
+        let vectors = if cache_handle == INVALID_HANDLE {
+            embedder.embed_as(kind, &texts)?
+        } else {
+            let cache = cache(cache_handle)?;
+            embedder.embed_cached(kind, &texts, &cache)?
+        };
+        out_slice(out_vectors, required, "out_vectors")?.copy_from_slice(&vectors);
+        Ok(count as i32)
+    })
+}
+
+/// Pools `[batch, seq, hidden_size]` states from a model run elsewhere over their attention
+/// `mask` with one of the `POOLING_*` strategies, keeps the first `dimensions` values (`0` for
+/// all) and optionally L2-normalizes. `out_vectors` receives `[batch, dimensions]` floats.
+#[no_mangle]
+pub extern "C" fn tokenizer_pool(
+    hidden: *const f32,
+    mask: *const i64,
+    batch: usize,
+    seq: usize,
+    hidden_size: usize,
+    pooling: u32,
+    dimensions: usize,
+    normalize: bool,
+    out_vectors: *mut f32,
+) -> i32 {
+    status(|| {
+        let pooling = Pooling::from_raw(pooling)?;
+        let dimensions = if dimensions == 0 {
+            hidden_size
+        } else {
+            dimensions
+        };
+        if hidden_size == 0 || dimensions > hidden_size {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("cannot pool {hidden_size}-dimensional states to {dimensions}"),
+            ));
+        }
+        let tokens = buffer_len(batch, seq)?;
+        let hidden = in_slice(hidden, buffer_len(tokens, hidden_size)?, "hidden")?;
+        let mask = in_slice(mask, tokens, "mask")?;
+        let mut vectors = pooling::pool(pooling, hidden, mask, batch, seq, hidden_size);
+        if dimensions < hidden_size {
+            vectors = pooling::truncate(&vectors, hidden_size, dimensions);
+        }
+        if normalize {
+            pooling::normalize(&mut vectors, dimensions);
+        }
+        out_slice(out_vectors, vectors.len(), "out_vectors")?.copy_from_slice(&vectors);
+        Ok(0)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_embedder_free(handle: Handle) -> i32 {
+    status(|| {
+        if EMBEDDERS.remove(handle) {
+            Ok(0)
+        } else {
+            Err(Error::new(
+                ErrorCode::NotInitialized,
+                format!("unknown embedder handle {handle}"),
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::{tokenizer_create, tokenizer_free};
+    use std::ffi::CString;
+
+    fn fixture(name: &str) -> CString {
+        CString::new(format!("{}/testdata/{name}", env!("CARGO_MANIFEST_DIR"))).unwrap()
+    }
+
+    #[test]
+    fn configured_padding_does_not_change_embeddings() {
+        let texts = [
+            CString::new("hello").unwrap(),
+            CString::new("the quick brown fox jumps").unwrap(),
+        ];
+        let pointers: Vec<_> = texts.iter().map(|t| t.as_ptr()).collect();
+        let embed = |name| {
+            let tokenizer = tokenizer_create(fixture(name).as_ptr());
+            let handle = tokenizer_embedder_create(fixture("encoder.onnx").as_ptr(), tokenizer, 0);
+            let mut vectors = [0f32; 8];
+            assert_eq!(
+                tokenizer_embed(handle, pointers.as_ptr(), 2, vectors.as_mut_ptr(), 8),
+                2
+            );
+            tokenizer_embedder_free(handle);
+            tokenizer_free(tokenizer);
+            vectors
+        };
+        assert_eq!(embed("tokenizer_configured.json"), embed("tokenizer.json"));
+    }
+
+    #[test]
+    fn embeds_a_batch_in_one_call() {
+        let tokenizer = tokenizer_create(fixture("tokenizer.json").as_ptr());
+        let handle = tokenizer_embedder_create(fixture("encoder.onnx").as_ptr(), tokenizer, 0);
+        assert_ne!(handle, INVALID_HANDLE);
+        let mut dim = 0;
+        assert_eq!(tokenizer_embedder_dim(handle, &mut dim), 0);
+        assert_eq!(dim, 4);
+
+        let texts = [
+            CString::new("hello").unwrap(),
+            CString::new("lazy dog").unwrap(),
+        ];
+        let pointers: Vec<_> = texts.iter().map(|t| t.as_ptr()).collect();
+        let mut vectors = [0f32; 8];
+        assert_eq!(
+            tokenizer_embed(handle, pointers.as_ptr(), 2, vectors.as_mut_ptr(), 8),
+            2
+        );
+        for row in vectors.chunks_exact(4) {
+            let norm: f32 = row.iter().map(|x| x * x).sum();
+            assert!((norm - 1.0).abs() < 1e-5);
+        }
+        assert_eq!(
+            tokenizer_embed(handle, pointers.as_ptr(), 2, vectors.as_mut_ptr(), 7),
+            ErrorCode::BufferTooSmall as i32
+        );
+        assert_eq!(tokenizer_embedder_free(handle), 0);
+        tokenizer_free(tokenizer);
+    }
+
+    #[test]
+    fn reports_profile_version_and_prepared_text() {
+        let tokenizer = tokenizer_create(fixture("tokenizer.json").as_ptr());
+        let handle = tokenizer_embedder_create_with_profile(
+            fixture("encoder.onnx").as_ptr(),
+            tokenizer,
+            std::ptr::null(),
+        );
+        assert_ne!(handle, INVALID_HANDLE);
+        let mut buf = [0u8; 32];
+        let mut required = 0;
+        let len = tokenizer_embedder_version(handle, buf.as_mut_ptr(), buf.len(), &mut required);
+        assert_eq!(&buf[..len as usize], b"tiny-encoder/1");
+
+        let text = CString::new("kot").unwrap();
+        let len = tokenizer_embedder_prepare_text(
+            handle,
+            crate::profile::TEXT_DOCUMENT,
+            text.as_ptr(),
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut required,
+        );
+        assert_eq!(&buf[..len as usize], b"passage: kot");
+
+        let mut vector = [0f32; 4];
+        assert_eq!(
+            tokenizer_embed_ex(handle, &text.as_ptr(), 1, 7, vector.as_mut_ptr(), 4),
+            ErrorCode::InvalidArgument as i32
+        );
+        tokenizer_embedder_free(handle);
+        tokenizer_free(tokenizer);
+    }
+
+    #[test]
+    fn counts_cache_hits_and_misses() {
+        use crate::cache::CacheStats;
+        use crate::ffi::cache::{
+            tokenizer_embedding_cache_create, tokenizer_embedding_cache_free,
+            tokenizer_embedding_cache_stats,
+        };
+
+        let tokenizer = tokenizer_create(fixture("tokenizer.json").as_ptr());
+        let handle = tokenizer_embedder_create(fixture("encoder.onnx").as_ptr(), tokenizer, 0);
+        let cache = tokenizer_embedding_cache_create(0, std::ptr::null());
+        assert_ne!(cache, INVALID_HANDLE);
+        let text = CString::new("hello world").unwrap();
+        let mut vector = [0f32; 4];
+        for _ in 0..3 {
+            assert_eq!(
+                tokenizer_embed_cached(
+                    handle,
+                    cache,
+                    &text.as_ptr(),
+                    1,
+                    TEXT_RAW,
+                    vector.as_mut_ptr(),
+                    4
+                ),
+                1
+            );
+        }
+        let mut stats = CacheStats::default();
+        assert_eq!(tokenizer_embedding_cache_stats(cache, &mut stats), 0);
+        assert_eq!((stats.hits, stats.misses, stats.entries), (2, 1, 1));
+        tokenizer_embedding_cache_free(cache);
+        tokenizer_embedder_free(handle);
+        tokenizer_free(tokenizer);
+    }
+
+    #[test]
+    fn pools_external_states() {
+        let hidden = [3.0f32, 4.0, 1.0, 0.0, 0.0, 9.0, 9.0, 9.0];
+        let mask = [1i64, 1, 1, 0];
+        let mut vectors = [0f32; 2];
+        let pool = |pooling, dimensions, vectors: &mut [f32; 2]| {
+            tokenizer_pool(
+                hidden.as_ptr(),
+                mask.as_ptr(),
+                2,
+                2,
+                2,
+                pooling,
+                dimensions,
+                true,
+                vectors.as_mut_ptr(),
+            )
+        };
+        assert_eq!(pool(pooling::POOLING_CLS, 1, &mut vectors), 0);
+        assert_eq!(vectors, [1.0, 0.0]);
+        let mut full = [0f32; 4];
+        assert_eq!(
+            tokenizer_pool(
+                hidden.as_ptr(),
+                mask.as_ptr(),
+                2,
+                2,
+                2,
+                pooling::POOLING_MAX,
+                0,
+                false,
+                full.as_mut_ptr(),
+            ),
+            0
+        );
+        assert_eq!(full, [3.0, 4.0, 0.0, 9.0]);
+        assert_eq!(
+            pool(pooling::POOLING_MEAN, 3, &mut vectors),
+            ErrorCode::InvalidArgument as i32
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use super::{guarded, in_slice, out_ref, out_slice, status, str_arg};
+use crate::error::{self, Error, ErrorCode};
+use crate::hnsw::{self, Hnsw};
+use crate::registry::{Handle, Registry, INVALID_HANDLE};
+use crate::similarity::Metric;
+use std::os::raw::c_char;
+use std::sync::{Arc, PoisonError, RwLock};
+
+static INDEXES: Registry<RwLock<Hnsw>> = Registry::new();
+
+/// Drops every handle; see `tokenizer_cleanup`.
+pub(super) fn clear() {
+    INDEXES.clear();
+}
+
+pub(super) fn index(handle: Handle) -> Result<Arc<RwLock<Hnsw>>, Error> {
+    INDEXES.get(handle).ok_or_else(|| {
+        Error::new(
+            ErrorCode::NotInitialized,
+            format!("unknown index handle {handle}"),
+        )
+    })
+}
+
+fn or_default(value: usize, default: usize) -> usize {
+    if value == 0 {
+        default
+    } else {
+        value
+    }
+}
+
+fn insert(index: Hnsw) -> Handle {
+    INDEXES.insert(RwLock::new(index))
+}
+
+fn report_handle(result: Result<Handle, Error>) -> Handle {
+    result.unwrap_or_else(|e| {
+        error::report(e);
+        INVALID_HANDLE
+    })
+}
+
+/// Creates an empty index for `dim`-dimensional vectors. `m` and `ef_construction` may be `0`
+/// for the defaults. Returns `0` on failure.
+#[no_mangle]
+pub extern "C" fn tokenizer_hnsw_create(
+    dim: usize,
+    metric: u32,
+    m: usize,
+    ef_construction: usize,
+) -> Handle {
+    report_handle(guarded(|| {
+        let index = Hnsw::new(
+            dim,
+            Metric::from_raw(metric)?,
+            or_default(m, hnsw::DEFAULT_M),
+            or_default(ef_construction, hnsw::DEFAULT_EF_CONSTRUCTION),
+        )?;
+        Ok(insert(index))
+    }))
+}
+
+/// Loads an index written by `tokenizer_hnsw_save`. Returns `0` on failure.
+#[no_mangle]
+pub extern "C" fn tokenizer_hnsw_load(path: *const c_char) -> Handle {
+    report_handle(guarded(|| Ok(insert(Hnsw::load(str_arg(path, "path")?)?))))
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_hnsw_save(handle: Handle, path: *const c_char) -> i32 {
+    status(|| {
+        let path = str_arg(path, "path")?;
+        let index = index(handle)?;
+        index
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .save(path)?;
+        Ok(0)
+    })
+}
+
+/// Writes the vector dimension and the number of indexed messages.
+#[no_mangle]
+pub extern "C" fn tokenizer_hnsw_info(
+    handle: Handle,
+    out_dim: *mut usize,
+    out_count: *mut usize,
+) -> i32 {
+    status(|| {
+        let out_dim = out_ref(out_dim, "out_dim")?;
+        let out_count = out_ref(out_count, "out_count")?;
+        let index = index(handle)?;
+        let index = index.read().unwrap_or_else(PoisonError::into_inner);
+        *out_dim = index.dim();
+        *out_count = index.len();
+        Ok(0)
+    })
+}
+
+/// Indexes a message; fails with `InvalidArgument` if the id is already present.
+#[no_mangle]
+pub extern "C" fn tokenizer_hnsw_add(
+    handle: Handle,
+    id: i64,
+    vector: *const f32,
+    dim: usize,
+) -> i32 {
+    status(|| {
+        let vector = in_slice(vector, dim, "vector")?;
+        let index = index(handle)?;
+        index
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .add(id, vector)?;
+        Ok(0)
+    })
+}
+
+/// Replaces a message's vector, adding it if it was not indexed.
+#[no_mangle]
+pub extern "C" fn tokenizer_hnsw_update(
+    handle: Handle,
+    id: i64,
+    vector: *const f32,
+    dim: usize,
+) -> i32 {
+    status(|| {
+        let vector = in_slice(vector, dim, "vector")?;
+        let index = index(handle)?;
+        index
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .update(id, vector)?;
+        Ok(0)
+    })
+}
+
+/// Returns `1` if the message was removed and `0` if it was not indexed.
+#[no_mangle]
+pub extern "C" fn tokenizer_hnsw_remove(handle: Handle, id: i64) -> i32 {
+    status(|| {
+        let index = index(handle)?;
+        let removed = index
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(id);
+        Ok(removed as i32)
+    })
+}
+
+/// Writes up to `k` message ids and scores, best first, and returns how many were written.
+/// Scores below `threshold` (above it for `METRIC_L2` distances) are skipped; pass NaN to keep all.
+/// `ef` trades speed for recall and may be `0` for the default.
+#[no_mangle]
+pub extern "C" fn tokenizer_hnsw_search(
+    handle: Handle,
+    query: *const f32,
+    dim: usize,
+    k: usize,
+    ef: usize,
+    threshold: f32,
+    out_ids: *mut i64,
+    out_scores: *mut f32,
+) -> i32 {
+    status(|| {
+        let query = in_slice(query, dim, "query")?;
+        let threshold = (!threshold.is_nan()).then_some(threshold);
+        let index = index(handle)?;
+        let found = index
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .search(query, k, or_default(ef, hnsw::DEFAULT_EF_SEARCH), threshold)?;
+        let ids = out_slice(out_ids, found.len(), "out_ids")?;
+        let scores = out_slice(out_scores, found.len(), "out_scores")?;
+        for (i, (id, score)) in found.iter().enumerate() {
+            ids[i] = *id;
+            scores[i] = *score;
+        }
+        Ok(found.len() as i32)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_hnsw_free(handle: Handle) -> i32 {
+    status(|| {
+        if INDEXES.remove(handle) {
+            Ok(0)
+        } else {
+            Err(Error::new(
+                ErrorCode::NotInitialized,
+                format!("unknown index handle {handle}"),
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::similarity::METRIC_COSINE;
+    use std::ffi::CString;
+
+    #[test]
+    fn indexes_searches_and_persists_by_message_id() {
+        let handle = tokenizer_hnsw_create(3, METRIC_COSINE, 0, 0);
+        assert_ne!(handle, INVALID_HANDLE);
+        let vectors = [[1.0f32, 0.0, 0.0], [0.0, 1.0, 0.0], [0.7, 0.7, 0.0]];
+        for (id, v) in vectors.iter().enumerate() {
+            assert_eq!(
+                tokenizer_hnsw_add(handle, id as i64 + 100, v.as_ptr(), 3),
+                0
+            );
+        }
+        assert_eq!(
+            tokenizer_hnsw_add(handle, 100, vectors[0].as_ptr(), 2),
+            ErrorCode::InvalidArgument as i32
+        );
+
+        let mut ids = [0i64; 3];
+        let mut scores = [0f32; 3];
+        let query = [1.0f32, 0.1, 0.0];
+        let search = |ids: &mut [i64; 3], scores: &mut [f32; 3], handle| {
+            tokenizer_hnsw_search(
+                handle,
+                query.as_ptr(),
+                3,
+                3,
+                0,
+                0.5,
+                ids.as_mut_ptr(),
+                scores.as_mut_ptr(),
+            )
+        };
+        assert_eq!(search(&mut ids, &mut scores, handle), 2);
+        assert_eq!(&ids[..2], [100, 102]);
+
+        assert_eq!(tokenizer_hnsw_remove(handle, 100), 1);
+        assert_eq!(tokenizer_hnsw_remove(handle, 100), 0);
+        let path = std::env::temp_dir().join(format!("hnsw-ffi-{}.bin", std::process::id()));
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(tokenizer_hnsw_save(handle, path.as_ptr()), 0);
+        tokenizer_hnsw_free(handle);
+
+        let loaded = tokenizer_hnsw_load(path.as_ptr());
+        std::fs::remove_file(path.to_str().unwrap()).unwrap();
+        let (mut dim, mut count) = (0, 0);
+        assert_eq!(tokenizer_hnsw_info(loaded, &mut dim, &mut count), 0);
+        assert_eq!((dim, count), (3, 2));
+        assert_eq!(search(&mut ids, &mut scores, loaded), 1);
+        assert_eq!(ids[0], 102);
+        tokenizer_hnsw_free(loaded);
+    }
+}
@@ -0,0 +1,236 @@
+use super::{buffer_len, in_slice, out_ref, out_slice, status};
+use crate::error::{Error, ErrorCode};
+use crate::quantize;
+
+fn positive(value: usize, what: &str) -> Result<usize, Error> {
+    if value == 0 {
+        return Err(Error::new(
+            ErrorCode::InvalidArgument,
+            format!("{what} must be positive"),
+        ));
+    }
+    Ok(value)
+}
+
+fn write_results<T: Copy>(
+    best: &[(usize, T)],
+    out_indices: *mut usize,
+    out_values: *mut T,
+    what: &str,
+) -> Result<i32, Error> {
+    let indices = out_slice(out_indices, best.len(), "out_indices")?;
+    let values = out_slice(out_values, best.len(), what)?;
+    for (&(index, value), (out_index, out_value)) in
+        best.iter().zip(indices.iter_mut().zip(values.iter_mut()))
+    {
+        *out_index = index;
+        *out_value = value;
+    }
+    Ok(best.len() as i32)
+}
+
+/// Quantizes `dim` floats to int8 codes with one scale, so that `x ≈ code * scale`.
+#[no_mangle]
+pub extern "C" fn tokenizer_quantize_int8(
+    vector: *const f32,
+    dim: usize,
+    out_codes: *mut i8,
+    out_scale: *mut f32,
+) -> i32 {
+    status(|| {
+        let (codes, scale) = quantize::int8(in_slice(vector, dim, "vector")?);
+        out_slice(out_codes, dim, "out_codes")?.copy_from_slice(&codes);
+        *out_ref(out_scale, "out_scale")? = scale;
+        Ok(0)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_dequantize_int8(
+    codes: *const i8,
+    dim: usize,
+    scale: f32,
+    out_vector: *mut f32,
+) -> i32 {
+    status(|| {
+        let vector = quantize::dequantize_int8(in_slice(codes, dim, "codes")?, scale);
+        out_slice(out_vector, dim, "out_vector")?.copy_from_slice(&vector);
+        Ok(0)
+    })
+}
+
+/// Packs the signs of `dim` floats into `ceil(dim / 8)` bytes, least significant bit first.
+/// Returns the number of bytes written, or `BufferTooSmall` if `bits_len` is too short.
+#[no_mangle]
+pub extern "C" fn tokenizer_quantize_binary(
+    vector: *const f32,
+    dim: usize,
+    out_bits: *mut u8,
+    bits_len: usize,
+) -> i32 {
+    status(|| {
+        let required = quantize::binary_len(dim);
+        if bits_len < required {
+            return Err(Error::new(
+                ErrorCode::BufferTooSmall,
+                format!("{required} bytes are needed, {bits_len} were given"),
+            ));
+        }
+        let bits = quantize::binary(in_slice(vector, dim, "vector")?);
+        out_slice(out_bits, required, "out_bits")?.copy_from_slice(&bits);
+        Ok(required as i32)
+    })
+}
+
+/// Writes the `k` binary codes nearest to `query_bits` by Hamming distance, nearest first.
+/// Returns the number of results written.
+#[no_mangle]
+pub extern "C" fn tokenizer_hamming_top_k(
+    query_bits: *const u8,
+    rows_bits: *const u8,
+    row_count: usize,
+    row_bytes: usize,
+    k: usize,
+    out_indices: *mut usize,
+    out_distances: *mut u32,
+) -> i32 {
+    status(|| {
+        let row_bytes = positive(row_bytes, "row_bytes")?;
+        let query = in_slice(query_bits, row_bytes, "query_bits")?;
+        let rows = in_slice(rows_bits, buffer_len(row_count, row_bytes)?, "rows_bits")?;
+        let best = quantize::hamming_top_k(query, rows, k)?;
+        write_results(&best, out_indices, out_distances, "out_distances")
+    })
+}
+
+/// Writes the best `k` int8 rows by approximate dot product, best first.
+/// Returns the number of results written.
+#[no_mangle]
+pub extern "C" fn tokenizer_int8_top_k(
+    query_codes: *const i8,
+    query_scale: f32,
+    rows: *const i8,
+    scales: *const f32,
+    row_count: usize,
+    dim: usize,
+    k: usize,
+    out_indices: *mut usize,
+    out_scores: *mut f32,
+) -> i32 {
+    status(|| {
+        let dim = positive(dim, "dim")?;
+        let query = in_slice(query_codes, dim, "query_codes")?;
+        let rows = in_slice(rows, buffer_len(row_count, dim)?, "rows")?;
+        let scales = in_slice(scales, row_count, "scales")?;
+        let best = quantize::int8_top_k(query, query_scale, rows, scales, k)?;
+        write_results(&best, out_indices, out_scores, "out_scores")
+    })
+}
+
+/// Re-ranks `candidate_count` row indices from a coarse search by the dot product of the
+/// float query with the rows' int8 codes, and writes the best `k`, best first.
+/// Returns the number of results written.
+#[no_mangle]
+pub extern "C" fn tokenizer_int8_rescore(
+    query: *const f32,
+    dim: usize,
+    candidates: *const usize,
+    candidate_count: usize,
+    rows: *const i8,
+    scales: *const f32,
+    row_count: usize,
+    k: usize,
+    out_indices: *mut usize,
+    out_scores: *mut f32,
+) -> i32 {
+    status(|| {
+        let dim = positive(dim, "dim")?;
+        let query = in_slice(query, dim, "query")?;
+        let candidates = in_slice(candidates, candidate_count, "candidates")?;
+        let rows = in_slice(rows, buffer_len(row_count, dim)?, "rows")?;
+        let scales = in_slice(scales, row_count, "scales")?;
+        let best = quantize::rescore(query, candidates, rows, scales, k)?;
+        write_results(&best, out_indices, out_scores, "out_scores")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_search_then_rescore() {
+        let rows = [[0.9f32, -0.1, 0.2], [-0.5, 0.5, 0.5], [0.7, 0.1, 0.1]];
+        let query = [0.8f32, 0.05, 0.1];
+        let mut bits = [0u8; 3];
+        let mut codes = [0i8; 9];
+        let mut scales = [0f32; 3];
+        for (i, row) in rows.iter().enumerate() {
+            assert_eq!(
+                tokenizer_quantize_binary(row.as_ptr(), 3, &mut bits[i], 1),
+                1
+            );
+            assert_eq!(
+                tokenizer_quantize_int8(
+                    row.as_ptr(),
+                    3,
+                    codes[i * 3..].as_mut_ptr(),
+                    &mut scales[i]
+                ),
+                0
+            );
+        }
+        let mut query_bits = 0u8;
+        tokenizer_quantize_binary(query.as_ptr(), 3, &mut query_bits, 1);
+
+        let mut indices = [usize::MAX; 2];
+        let mut distances = [0u32; 2];
+        let written = tokenizer_hamming_top_k(
+            &query_bits,
+            bits.as_ptr(),
+            3,
+            1,
+            2,
+            indices.as_mut_ptr(),
+            distances.as_mut_ptr(),
+        );
+        assert_eq!(written, 2);
+        assert_eq!(indices, [2, 0]);
+
+        let mut all = [usize::MAX; 3];
+        let mut all_distances = [0u32; 3];
+        let written = tokenizer_hamming_top_k(
+            &query_bits,
+            bits.as_ptr(),
+            3,
+            1,
+            usize::MAX,
+            all.as_mut_ptr(),
+            all_distances.as_mut_ptr(),
+        );
+        assert_eq!(written, 3);
+
+        let mut best = [usize::MAX];
+        let mut score = [0f32];
+        let written = tokenizer_int8_rescore(
+            query.as_ptr(),
+            3,
+            indices.as_ptr(),
+            2,
+            codes.as_ptr(),
+            scales.as_ptr(),
+            3,
+            1,
+            best.as_mut_ptr(),
+            score.as_mut_ptr(),
+        );
+        assert_eq!(written, 1);
+        assert_eq!(best, [0]);
+        assert!((score[0] - 0.735).abs() < 0.01);
+
+        assert_eq!(
+            tokenizer_quantize_binary(query.as_ptr(), 9, &mut query_bits, 1),
+            ErrorCode::BufferTooSmall as i32
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use super::{buffer_len, in_slice, out_ref, out_slice, status};
+use crate::error::{Error, ErrorCode};
+use crate::similarity::{self, Metric};
+
+fn pair<'a>(a: *const f32, b: *const f32, dim: usize) -> Result<(&'a [f32], &'a [f32]), Error> {
+    Ok((in_slice(a, dim, "a")?, in_slice(b, dim, "b")?))
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_vector_cosine(
+    a: *const f32,
+    b: *const f32,
+    dim: usize,
+    out_score: *mut f32,
+) -> i32 {
+    status(|| {
+        let (a, b) = pair(a, b, dim)?;
+        *out_ref(out_score, "out_score")? = similarity::cosine(a, b);
+        Ok(0)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_vector_dot(
+    a: *const f32,
+    b: *const f32,
+    dim: usize,
+    out_score: *mut f32,
+) -> i32 {
+    status(|| {
+        let (a, b) = pair(a, b, dim)?;
+        *out_ref(out_score, "out_score")? = similarity::dot(a, b);
+        Ok(0)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_vector_l2(
+    a: *const f32,
+    b: *const f32,
+    dim: usize,
+    out_distance: *mut f32,
+) -> i32 {
+    status(|| {
+        let (a, b) = pair(a, b, dim)?;
+        *out_ref(out_distance, "out_distance")? = similarity::l2_squared(a, b).sqrt();
+        Ok(0)
+    })
+}
+
+/// Scores `query` against `row_count` rows stored back to back in `rows` (`row_count * dim`
+/// floats) and writes the best `k` row indices and scores, best first. For `METRIC_L2` the
+/// scores are distances and smaller is better. Returns the number of results written.
+#[no_mangle]
+pub extern "C" fn tokenizer_vector_top_k(
+    query: *const f32,
+    rows: *const f32,
+    row_count: usize,
+    dim: usize,
+    metric: u32,
+    k: usize,
+    out_indices: *mut usize,
+    out_scores: *mut f32,
+) -> i32 {
+    status(|| {
+        let metric = Metric::from_raw(metric)?;
+        if dim == 0 {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                "dim must be positive",
+            ));
+        }
+        let query = in_slice(query, dim, "query")?;
+        let rows = in_slice(rows, buffer_len(row_count, dim)?, "rows")?;
+        let best = similarity::top_k(query, rows, metric, k)?;
+        let indices = out_slice(out_indices, best.len(), "out_indices")?;
+        let scores = out_slice(out_scores, best.len(), "out_scores")?;
+        for ((index, score), (out_index, out_score)) in
+            best.iter().zip(indices.iter_mut().zip(scores.iter_mut()))
+        {
+            *out_index = *index;
+            *out_score = *score;
+        }
+        Ok(best.len() as i32)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::similarity::METRIC_COSINE;
+
+    #[test]
+    fn top_k_writes_indices_and_scores() {
+        let query = [1.0f32, 0.0];
+        let rows = [0.0f32, 1.0, 1.0, 0.0, 0.5, 0.5];
+        let mut indices = [usize::MAX; 2];
+        let mut scores = [0f32; 2];
+        let written = tokenizer_vector_top_k(
+            query.as_ptr(),
+            rows.as_ptr(),
+            3,
+            2,
+            METRIC_COSINE,
+            2,
+            indices.as_mut_ptr(),
+            scores.as_mut_ptr(),
+        );
+        assert_eq!(written, 2);
+        assert_eq!(indices, [1, 2]);
+        assert_eq!(scores[0], 1.0);
+
+        assert_eq!(
+            tokenizer_vector_top_k(
+                query.as_ptr(),
+                rows.as_ptr(),
+                3,
+                2,
+                7,
+                2,
+                indices.as_mut_ptr(),
+                scores.as_mut_ptr(),
+            ),
+            ErrorCode::InvalidArgument as i32
+        );
+
+        let mut distance = 0f32;
+        assert_eq!(
+            tokenizer_vector_l2(query.as_ptr(), rows.as_ptr(), 2, &mut distance),
+            0
+        );
+        assert!((distance - 2f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn top_k_with_huge_k_returns_every_row() {
+        let query = [1.0f32, 0.0];
+        let rows = [0.0f32, 1.0, 1.0, 0.0, 0.5, 0.5];
+        let mut indices = [usize::MAX; 3];
+        let mut scores = [0f32; 3];
+        let written = tokenizer_vector_top_k(
+            query.as_ptr(),
+            rows.as_ptr(),
+            3,
+            2,
+            METRIC_COSINE,
+            usize::MAX,
+            indices.as_mut_ptr(),
+            scores.as_mut_ptr(),
+        );
+        assert_eq!(written, 3);
+        assert_eq!(indices, [1, 2, 0]);
+    }
+}
@@ -0,0 +1,212 @@
+use super::{guarded, in_slice, out_ref, status, str_arg};
+use crate::error::{self, Error, ErrorCode};
+use crate::registry::{Handle, Registry, INVALID_HANDLE};
+use crate::stop::{StopMatcher, StopReason, StopStatus};
+use std::os::raw::c_char;
+use std::sync::{Arc, Mutex, PoisonError};
+
+static MATCHERS: Registry<Mutex<StopMatcher>> = Registry::new();
+
+/// Drops every handle; see `tokenizer_cleanup`.
+pub(super) fn clear() {
+    MATCHERS.clear();
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct StopResult {
+    pub stopped: bool,
+    /// Index of the matched stop string, or `-1`.
+    pub stop_string: i32,
+    /// Matched stop token id, or `-1`.
+    pub stop_token: i64,
+    /// Trailing text of everything pushed so far that must not be displayed.
+    pub withheld_bytes: usize,
+    pub withheld_utf16: usize,
+}
+
+impl From<StopStatus> for StopResult {
+    fn from(status: StopStatus) -> Self {
+        StopResult {
+            stopped: status.stopped.is_some(),
+            stop_string: match status.stopped {
+                Some(StopReason::Text(index)) => index as i32,
+                _ => -1,
+            },
+            stop_token: match status.stopped {
+                Some(StopReason::Token(id)) => id as i64,
+                _ => -1,
+            },
+            withheld_bytes: status.withheld_bytes,
+            withheld_utf16: status.withheld_utf16,
+        }
+    }
+}
+
+fn matcher(handle: Handle) -> Result<Arc<Mutex<StopMatcher>>, Error> {
+    MATCHERS.get(handle).ok_or_else(|| {
+        Error::new(
+            ErrorCode::NotInitialized,
+            format!("unknown stop matcher handle {handle}"),
+        )
+    })
+}
+
+fn token_id(id: i32) -> Result<u32, Error> {
+    u32::try_from(id)
+        .map_err(|_| Error::new(ErrorCode::InvalidArgument, format!("invalid token id {id}")))
+}
+
+/// Creates a stop matcher from `string_count` stop strings and `id_count` stop token ids.
+/// Returns `0` on failure.
+#[no_mangle]
+pub extern "C" fn tokenizer_stop_create(
+    stop_strings: *const *const c_char,
+    string_count: usize,
+    stop_ids: *const i32,
+    id_count: usize,
+) -> Handle {
+    guarded(|| {
+        let strings = in_slice(stop_strings, string_count, "stop_strings")?
+            .iter()
+            .map(|&s| str_arg(s, "stop string").map(str::to_string))
+            .collect::<Result<_, _>>()?;
+        let ids = in_slice(stop_ids, id_count, "stop_ids")?
+            .iter()
+            .map(|&id| token_id(id))
+            .collect::<Result<_, _>>()?;
+        Ok(MATCHERS.insert(Mutex::new(StopMatcher::new(strings, ids)?)))
+    })
+    .unwrap_or_else(|e| {
+        error::report(e);
+        INVALID_HANDLE
+    })
+}
+
+/// Scans newly generated text. Pass the same deltas that are appended to the displayed output.
+#[no_mangle]
+pub extern "C" fn tokenizer_stop_push_text(
+    handle: Handle,
+    text: *const c_char,
+    out_result: *mut StopResult,
+) -> i32 {
+    status(|| {
+        let text = str_arg(text, "text")?;
+        let out_result = out_ref(out_result, "out_result")?;
+        let matcher = matcher(handle)?;
+        let status = matcher
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push_text(text);
+        *out_result = status.into();
+        Ok(0)
+    })
+}
+
+/// Checks a generated id against the stop token ids.
+#[no_mangle]
+pub extern "C" fn tokenizer_stop_push_token(
+    handle: Handle,
+    id: i32,
+    out_result: *mut StopResult,
+) -> i32 {
+    status(|| {
+        let out_result = out_ref(out_result, "out_result")?;
+        let id = token_id(id)?;
+        let matcher = matcher(handle)?;
+        let status = matcher
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push_token(id);
+        *out_result = status.into();
+        Ok(0)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_stop_reset(handle: Handle) -> i32 {
+    status(|| {
+        matcher(handle)?
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .reset();
+        Ok(0)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_stop_free(handle: Handle) -> i32 {
+    status(|| {
+        if MATCHERS.remove(handle) {
+            Ok(0)
+        } else {
+            Err(Error::new(
+                ErrorCode::NotInitialized,
+                format!("unknown stop matcher handle {handle}"),
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::ptr;
+
+    #[test]
+    fn stop_matcher_round_trip() {
+        let strings = [CString::new("</s>").unwrap()];
+        let ptrs = [strings[0].as_ptr()];
+        assert_eq!(
+            tokenizer_stop_create(ptrs.as_ptr(), 1, [-1].as_ptr(), 1),
+            INVALID_HANDLE
+        );
+        assert_eq!(error::last_error_code(), ErrorCode::InvalidArgument);
+
+        let handle = tokenizer_stop_create(ptrs.as_ptr(), 1, [7].as_ptr(), 1);
+        assert_ne!(handle, INVALID_HANDLE);
+        let mut result = StopResult::from(StopStatus {
+            stopped: None,
+            withheld_bytes: 0,
+            withheld_utf16: 0,
+        });
+        let text = CString::new("żółw </").unwrap();
+        assert_eq!(
+            tokenizer_stop_push_text(handle, text.as_ptr(), &mut result),
+            0
+        );
+        assert!(!result.stopped);
+        assert_eq!((result.withheld_bytes, result.withheld_utf16), (2, 2));
+
+        assert_eq!(
+            tokenizer_stop_push_token(handle, -3, &mut result),
+            ErrorCode::InvalidArgument as i32
+        );
+        assert_eq!(tokenizer_stop_push_token(handle, 5, &mut result), 0);
+        assert!(!result.stopped);
+        assert_eq!(tokenizer_stop_push_token(handle, 7, &mut result), 0);
+        assert!(result.stopped);
+        assert_eq!((result.stop_token, result.stop_string), (7, -1));
+        assert_eq!(result.withheld_bytes, 0);
+
+        assert_eq!(tokenizer_stop_reset(handle), 0);
+        let text = CString::new("done</s> extra").unwrap();
+        assert_eq!(
+            tokenizer_stop_push_text(handle, text.as_ptr(), &mut result),
+            0
+        );
+        assert!(result.stopped);
+        assert_eq!((result.stop_string, result.withheld_bytes), (0, 10));
+        assert_eq!(
+            tokenizer_stop_push_text(handle, ptr::null(), &mut result),
+            ErrorCode::NullPointer as i32
+        );
+
+        assert_eq!(tokenizer_stop_free(handle), 0);
+        assert_eq!(
+            tokenizer_stop_free(handle),
+            ErrorCode::NotInitialized as i32
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use super::{guarded, out_ref, status, tokenizer, write_utf8};
+use crate::error::{self, Error, ErrorCode};
+use crate::registry::{Handle, Registry, INVALID_HANDLE};
+use crate::stream::StreamDecoder;
+use std::sync::{Arc, Mutex, PoisonError};
+
+static STREAMS: Registry<Mutex<Stream>> = Registry::new();
+
+/// Drops every handle; see `tokenizer_cleanup`.
+pub(super) fn clear() {
+    STREAMS.clear();
+}
+
+struct Stream {
+    decoder: StreamDecoder,
+    /// Text produced by `step` that did not fit in the caller's buffer yet.
+    pending: String,
+}
+
+fn stream(handle: Handle) -> Result<Arc<Mutex<Stream>>, Error> {
+    STREAMS.get(handle).ok_or_else(|| {
+        Error::new(
+            ErrorCode::NotInitialized,
+            format!("unknown stream handle {handle}"),
+        )
+    })
+}
+
+fn take_pending(
+    stream: &mut Stream,
+    out_buf: *mut u8,
+    buf_len: usize,
+    out_required: &mut usize,
+) -> Result<i32, Error> {
+    let written = write_utf8(&stream.pending, out_buf, buf_len, out_required)?;
+    stream.pending.clear();
+    Ok(written)
+}
+
+/// Creates a streaming decoder bound to a tokenizer. Returns `0` on failure.
+#[no_mangle]
+pub extern "C" fn tokenizer_stream_create(handle: Handle, skip_special_tokens: bool) -> Handle {
+    guarded(|| {
+        let decoder = StreamDecoder::new(tokenizer(handle)?, skip_special_tokens);
+        Ok(STREAMS.insert(Mutex::new(Stream {
+            decoder,
+            pending: String::new(),
+        })))
+    })
+    .unwrap_or_else(|e| {
+        error::report(e);
+        INVALID_HANDLE
+    })
+}
+
+/// Feeds one generated id and writes the newly completed UTF-8 text, returning its length in bytes.
+/// `0` means the id did not complete any character yet. If the text does not fit, `BufferTooSmall`
+/// is returned with the size in `out_required` and the text is kept for `tokenizer_stream_take`.
+#[no_mangle]
+pub extern "C" fn tokenizer_stream_step(
+    stream_handle: Handle,
+    id: i32,
+    out_buf: *mut u8,
+    buf_len: usize,
+    out_required: *mut usize,
+) -> i32 {
+    status(|| {
+        let out_required = out_ref(out_required, "out_required")?;
+        let id = u32::try_from(id).map_err(|_| {
+            Error::new(ErrorCode::InvalidArgument, format!("invalid token id {id}"))
+        })?;
+        let stream = stream(stream_handle)?;
+        let mut stream = stream.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(delta) = stream.decoder.step(id)? {
+            stream.pending.push_str(&delta);
+        }
+        take_pending(&mut stream, out_buf, buf_len, out_required)
+    })
+}
+
+/// Writes text left over from a step that returned `BufferTooSmall`.
+#[no_mangle]
+pub extern "C" fn tokenizer_stream_take(
+    stream_handle: Handle,
+    out_buf: *mut u8,
+    buf_len: usize,
+    out_required: *mut usize,
+) -> i32 {
+    status(|| {
+        let out_required = out_ref(out_required, "out_required")?;
+        let stream = stream(stream_handle)?;
+        let mut stream = stream.lock().unwrap_or_else(PoisonError::into_inner);
+        take_pending(&mut stream, out_buf, buf_len, out_required)
+    })
+}
+
+/// Forgets all ids seen so far, so the decoder can be reused for the next response.
+#[no_mangle]
+pub extern "C" fn tokenizer_stream_reset(stream_handle: Handle) -> i32 {
+    status(|| {
+        let stream = stream(stream_handle)?;
+        let mut stream = stream.lock().unwrap_or_else(PoisonError::into_inner);
+        stream.decoder.reset();
+        stream.pending.clear();
+        Ok(0)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn tokenizer_stream_free(stream_handle: Handle) -> i32 {
+    status(|| {
+        if STREAMS.remove(stream_handle) {
+            Ok(0)
+        } else {
+            Err(Error::new(
+                ErrorCode::NotInitialized,
+                format!("unknown stream handle {stream_handle}"),
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::{tokenizer_create, tokenizer_free};
+    use std::ffi::CString;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/byte_fallback.json");
+
+    #[test]
+    fn undersized_buffer_keeps_text_for_take() {
+        let handle = tokenizer_create(CString::new(FIXTURE).unwrap().as_ptr());
+        let stream = tokenizer_stream_create(handle, true);
+        assert_ne!(stream, INVALID_HANDLE);
+        let hello = crate::registry::get(handle)
+            .unwrap()
+            .token_to_id("▁Hello")
+            .unwrap() as i32;
+
+        let mut buf = [0u8; 16];
+        let mut required = 0;
+        assert_eq!(
+            tokenizer_stream_step(stream, hello, buf.as_mut_ptr(), 2, &mut required),
+            ErrorCode::BufferTooSmall as i32
+        );
+        assert_eq!(required, 5);
+        assert_eq!(
+            tokenizer_stream_take(stream, buf.as_mut_ptr(), buf.len(), &mut required),
+            5
+        );
+        assert_eq!(&buf[..5], b"Hello");
+        assert_eq!(
+            tokenizer_stream_step(stream, -3, buf.as_mut_ptr(), buf.len(), &mut required),
+            ErrorCode::InvalidArgument as i32
+        );
+
+        assert_eq!(tokenizer_stream_free(stream), 0);
+        assert_eq!(
+            tokenizer_stream_free(stream),
+            ErrorCode::NotInitialized as i32
+        );
+        tokenizer_free(handle);
+    }
+}
@@ -0,0 +1,94 @@
+use super::{guarded, out_ref, status, str_arg, write_utf8};
+use crate::error;
+use crate::registry::{self, Handle, INVALID_HANDLE};
+use crate::tiktoken;
+use std::os::raw::c_char;
+
+/// Loads a `.tiktoken` rank file as a tokenizer usable with every handle-based function.
+/// `encoding` names its split pattern and special tokens, e.g. `cl100k_base` or `o200k_base`.
+/// Returns `0` on failure.
+#[no_mangle]
+pub extern "C" fn tokenizer_create_tiktoken(
+    path: *const c_char,
+    encoding: *const c_char,
+) -> Handle {
+    guarded(|| {
+        let encoding = tiktoken::encoding(str_arg(encoding, "encoding")?)?;
+        let tokenizer = tiktoken::load(str_arg(path, "path")?, encoding)?;
+        registry::insert(tokenizer)
+    })
+    .unwrap_or_else(|e| {
+        error::report(e);
+        INVALID_HANDLE
+    })
+}
+
+/// Writes the tiktoken encoding name used by an OpenAI model, e.g. `o200k_base` for `gpt-4o`.
+#[no_mangle]
+pub extern "C" fn tokenizer_tiktoken_encoding_for_model(
+    model: *const c_char,
+    out_buf: *mut u8,
+    buf_len: usize,
+    out_required: *mut usize,
+) -> i32 {
+    status(|| {
+        let out_required = out_ref(out_required, "out_required")?;
+        let encoding = tiktoken::encoding_for_model(str_arg(model, "model")?)?;
+        write_utf8(encoding.name, out_buf, buf_len, out_required)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use crate::ffi::{tokenizer_count_tokens_handle, tokenizer_decode_into, tokenizer_free};
+    use std::ffi::CString;
+
+    #[test]
+    fn tiktoken_handles_work_with_existing_exports() {
+        let path = CString::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/tiny.tiktoken"
+        ))
+        .unwrap();
+        let mut buf = [0u8; 32];
+        let mut required = 0;
+        let model = CString::new("gpt-4-turbo").unwrap();
+        let len = tokenizer_tiktoken_encoding_for_model(
+            model.as_ptr(),
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut required,
+        );
+        assert_eq!(&buf[..len as usize], b"cl100k_base");
+
+        let encoding = CString::new(&buf[..len as usize]).unwrap();
+        let handle = tokenizer_create_tiktoken(path.as_ptr(), encoding.as_ptr());
+        assert_ne!(handle, INVALID_HANDLE);
+        let text = CString::new("hello world").unwrap();
+        assert_eq!(
+            tokenizer_count_tokens_handle(handle, text.as_ptr(), true),
+            2
+        );
+        let ids = [259, 264];
+        let len = tokenizer_decode_into(
+            handle,
+            ids.as_ptr(),
+            ids.len(),
+            true,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut required,
+        );
+        assert_eq!(&buf[..len as usize], b"hello world");
+        tokenizer_free(handle);
+
+        let unknown = CString::new("p50k_base").unwrap();
+        assert_eq!(
+            tokenizer_create_tiktoken(path.as_ptr(), unknown.as_ptr()),
+            INVALID_HANDLE
+        );
+        assert_eq!(error::last_error_code(), ErrorCode::InvalidArgument);
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+
+pub const SOURCE_LEXICAL: u32 = 1;
+pub const SOURCE_SEMANTIC: u32 = 2;
+
+pub const DEFAULT_RRF_K: f32 = 60.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fused {
+    pub id: i64,
+    pub score: f32,
+    /// `SOURCE_*` flags of the result lists the message was found in.
+    pub source: u32,
+}
+
+/// Reciprocal rank fusion: each list contributes `1 / (k + rank)` for the ranks, starting at 1,
+/// at which it holds a message. Only ranks matter, so BM25 and similarity scores need no
+/// normalization. Returns the best `limit` messages, best first; ties go to the lower id.
+pub fn reciprocal_rank_fusion(
+    lexical: &[i64],
+    semantic: &[i64],
+    k: f32,
+    limit: usize,
+) -> Vec<Fused> {
+    let mut fused: HashMap<i64, Fused> = HashMap::new();
+    for (ids, source) in [(lexical, SOURCE_LEXICAL), (semantic, SOURCE_SEMANTIC)] {
+        for (rank, &id) in ids.iter().enumerate() {
+            let entry = fused.entry(id).or_insert(Fused {
+                id,
+                score: 0.0,
+                source: 0,
+            });
+            if entry.source & source == 0 {
+                entry.score += 1.0 / (k + rank as f32 + 1.0);
+                entry.source |= source;
+            }
+        }
+    }
+    let mut fused: Vec<Fused> = fused.into_values().collect();
+    fused.sort_unstable_by(|a, b| b.score.total_cmp(&a.score).then(a.id.cmp(&b.id)));
+    fused.truncate(limit);
+    fused
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_found_by_both_lists_rank_first() {
+        let fused = reciprocal_rank_fusion(&[7, 3, 9], &[3, 5, 7, 3], DEFAULT_RRF_K, 4);
+        let ids: Vec<i64> = fused.iter().map(|f| f.id).collect();
+        assert_eq!(ids, [3, 7, 5, 9]);
+        assert_eq!(fused[0].source, SOURCE_LEXICAL | SOURCE_SEMANTIC);
+        assert_eq!(fused[2].source, SOURCE_SEMANTIC);
+        assert_eq!(fused[3].source, SOURCE_LEXICAL);
+        assert!((fused[0].score - (1.0 / 62.0 + 1.0 / 61.0)).abs() < 1e-7);
+        assert!(reciprocal_rank_fusion(&[], &[], DEFAULT_RRF_K, 5).is_empty());
+    }
+}
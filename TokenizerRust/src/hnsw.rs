@@ -0,0 +1,624 @@
+use crate::error::{Error, ErrorCode};
+use crate::similarity::{self, Metric};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub const DEFAULT_M: usize = 16;
+pub const DEFAULT_EF_CONSTRUCTION: usize = 200;
+pub const DEFAULT_EF_SEARCH: usize = 64;
+
+const MAGIC: &[u8; 4] = b"HNSW";
+const VERSION: u32 = 1;
+const MAX_LEVEL: usize = 16;
+const MAX_M: usize = 256;
+const MAX_DIM: usize = 1 << 16;
+const NO_ENTRY: u32 = u32::MAX;
+
+/// Hierarchical navigable small world graph over message embeddings, keyed by message id.
+///
+/// Removed messages stay in the graph as tombstones so that searches can still route through
+/// them; the graph is rebuilt once tombstones outnumber live entries.
+pub struct Hnsw {
+    dim: usize,
+    metric: Metric,
+    m: usize,
+    ef_construction: usize,
+    nodes: Vec<Node>,
+    slots: HashMap<i64, u32>,
+    entry: Option<u32>,
+    deleted: usize,
+    rng: u64,
+}
+
+struct Node {
+    id: i64,
+    vector: Vec<f32>,
+    /// Neighbour slots per layer; the node's level is `links.len() - 1`.
+    links: Vec<Vec<u32>>,
+    deleted: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Near {
+    dist: f32,
+    slot: u32,
+}
+
+impl Eq for Near {}
+
+impl Ord for Near {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.dist
+            .total_cmp(&other.dist)
+            .then(self.slot.cmp(&other.slot))
+    }
+}
+
+impl PartialOrd for Near {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hnsw {
+    pub fn new(
+        dim: usize,
+        metric: Metric,
+        m: usize,
+        ef_construction: usize,
+    ) -> Result<Self, Error> {
+        if dim == 0 || dim > MAX_DIM || !(2..=MAX_M).contains(&m) {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("invalid index parameters: dim {dim}, m {m}"),
+            ));
+        }
+        Ok(Hnsw {
+            dim,
+            metric,
+            m,
+            ef_construction: ef_construction.max(m),
+            nodes: Vec::new(),
+            slots: HashMap::new(),
+            entry: None,
+            deleted: 0,
+            rng: 0x2545_f491_4f6c_dd1d,
+        })
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn contains(&self, id: i64) -> bool {
+        self.slots.contains_key(&id)
+    }
+
+    pub fn add(&mut self, id: i64, vector: &[f32]) -> Result<(), Error> {
+        if self.contains(id) {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("message {id} is already indexed"),
+            ));
+        }
+        let vector = self.prepare(vector)?;
+        self.insert(id, vector);
+        Ok(())
+    }
+
+    /// Replaces the vector of an indexed message, or adds it if it is missing.
+    pub fn update(&mut self, id: i64, vector: &[f32]) -> Result<(), Error> {
+        let vector = self.prepare(vector)?;
+        self.remove(id);
+        self.insert(id, vector);
+        Ok(())
+    }
+
+    /// Returns whether the message was indexed.
+    pub fn remove(&mut self, id: i64) -> bool {
+        let Some(slot) = self.slots.remove(&id) else {
+            return false;
+        };
+        self.nodes[slot as usize].deleted = true;
+        self.deleted += 1;
+        if self.deleted > self.slots.len() {
+            self.rebuild();
+        }
+        true
+    }
+
+    /// Returns up to `k` `(message id, score)` pairs, best first. Scores are similarities for
+    /// cosine and dot, where `threshold` is a minimum, and distances for L2, where it is a maximum.
+    pub fn search(
+        &self,
+        query: &[f32],
+        k: usize,
+        ef: usize,
+        threshold: Option<f32>,
+    ) -> Result<Vec<(i64, f32)>, Error> {
+        let query = self.prepare(query)?;
+        let Some(entry) = self.entry else {
+            return Ok(Vec::new());
+        };
+        let mut entries = vec![entry];
+        for level in (1..self.nodes[entry as usize].links.len()).rev() {
+            entries = vec![self.search_layer(&query, &entries, 1, level)[0].slot];
+        }
+        let found = self.search_layer(&query, &entries, ef.max(k), 0);
+        Ok(found
+            .into_iter()
+            .filter(|near| !self.nodes[near.slot as usize].deleted)
+            .map(|near| (self.nodes[near.slot as usize].id, self.score(near.dist)))
+            .filter(|&(_, score)| match (threshold, self.metric) {
+                (None, _) => true,
+                (Some(max), Metric::L2) => score <= max,
+                (Some(min), _) => score >= min,
+            })
+            .take(k)
+            .collect())
+    }
+
+    fn prepare(&self, vector: &[f32]) -> Result<Vec<f32>, Error> {
+        if vector.len() != self.dim {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("expected {} dimensions, got {}", self.dim, vector.len()),
+            ));
+        }
+        let mut vector = vector.to_vec();
+        if self.metric == Metric::Cosine {
+            let norm = similarity::dot(&vector, &vector).sqrt();
+            if norm > 0.0 {
+                vector.iter_mut().for_each(|x| *x /= norm);
+            }
+        }
+        Ok(vector)
+    }
+
+    /// Lower is closer, whatever the metric.
+    fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self.metric {
+            Metric::Cosine => 1.0 - similarity::dot(a, b),
+            Metric::Dot => -similarity::dot(a, b),
+            Metric::L2 => similarity::l2_squared(a, b),
+        }
+    }
+
+    fn score(&self, dist: f32) -> f32 {
+        match self.metric {
+            Metric::Cosine => 1.0 - dist,
+            Metric::Dot => -dist,
+            Metric::L2 => dist.sqrt(),
+        }
+    }
+
+    fn distance_to(&self, query: &[f32], slot: u32) -> f32 {
+        self.distance(query, &self.nodes[slot as usize].vector)
+    }
+
+    fn max_links(&self, level: usize) -> usize {
+        if level == 0 {
+            self.m * 2
+        } else {
+            self.m
+        }
+    }
+
+    fn random_level(&mut self) -> usize {
+        // xorshift64*
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let bits = self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11;
+        let uniform = (bits as f64 + 1.0) / (1u64 << 53) as f64;
+        ((-uniform.ln() / (self.m as f64).ln()) as usize).min(MAX_LEVEL)
+    }
+
+    fn insert(&mut self, id: i64, vector: Vec<f32>) {
+        let level = self.random_level();
+        let slot = self.nodes.len() as u32;
+        self.slots.insert(id, slot);
+        let Some(entry) = self.entry else {
+            self.nodes.push(Node {
+                id,
+                vector,
+                links: vec![Vec::new(); level + 1],
+                deleted: false,
+            });
+            self.entry = Some(slot);
+            return;
+        };
+
+        let top = self.nodes[entry as usize].links.len() - 1;
+        let mut entries = vec![entry];
+        for layer in (level + 1..=top).rev() {
+            entries = vec![self.search_layer(&vector, &entries, 1, layer)[0].slot];
+        }
+        let mut links = vec![Vec::new(); level + 1];
+        let mut layers = Vec::new();
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(&vector, &entries, self.ef_construction, layer);
+            links[layer] = self.select_neighbors(&found, self.m);
+            layers.push(layer);
+            entries = found.iter().map(|near| near.slot).collect();
+        }
+        self.nodes.push(Node {
+            id,
+            vector,
+            links,
+            deleted: false,
+        });
+        for layer in layers {
+            let max = self.max_links(layer);
+            for neighbor in self.nodes[slot as usize].links[layer].clone() {
+                self.nodes[neighbor as usize].links[layer].push(slot);
+                if self.nodes[neighbor as usize].links[layer].len() > max {
+                    self.shrink(neighbor, layer, max);
+                }
+            }
+        }
+        if level > top {
+            self.entry = Some(slot);
+        }
+    }
+
+    fn shrink(&mut self, slot: u32, layer: usize, max: usize) {
+        let vector = &self.nodes[slot as usize].vector;
+        let mut candidates: Vec<Near> = self.nodes[slot as usize].links[layer]
+            .iter()
+            .map(|&other| Near {
+                dist: self.distance_to(vector, other),
+                slot: other,
+            })
+            .collect();
+        candidates.sort_unstable();
+        let kept = self.select_neighbors(&candidates, max);
+        self.nodes[slot as usize].links[layer] = kept;
+    }
+
+    /// Keeps candidates that are closer to the new node than to any neighbour already chosen,
+    /// which preserves links across clusters, then fills up with the closest of the rest.
+    fn select_neighbors(&self, sorted: &[Near], m: usize) -> Vec<u32> {
+        let mut selected: Vec<u32> = Vec::with_capacity(m);
+        let mut pruned = Vec::new();
+        for near in sorted
+            .iter()
+            .filter(|near| !self.nodes[near.slot as usize].deleted)
+        {
+            if selected.len() == m {
+                break;
+            }
+            let vector = &self.nodes[near.slot as usize].vector;
+            if selected
+                .iter()
+                .all(|&s| self.distance_to(vector, s) > near.dist)
+            {
+                selected.push(near.slot);
+            } else {
+                pruned.push(near.slot);
+            }
+        }
+        let missing = m - selected.len();
+        selected.extend(pruned.into_iter().take(missing));
+        selected
+    }
+
+    /// Best-first search of one layer; returns up to `ef` nodes, closest first.
+    fn search_layer(&self, query: &[f32], entries: &[u32], ef: usize, layer: usize) -> Vec<Near> {
+        let mut visited: HashSet<u32> = entries.iter().copied().collect();
+        let mut candidates = BinaryHeap::new();
+        let mut results = BinaryHeap::new();
+        for &slot in entries {
+            let near = Near {
+                dist: self.distance_to(query, slot),
+                slot,
+            };
+            candidates.push(Reverse(near));
+            results.push(near);
+        }
+        while let Some(Reverse(current)) = candidates.pop() {
+            let worst = results.peek().map_or(f32::INFINITY, |n: &Near| n.dist);
+            if results.len() >= ef && current.dist > worst {
+                break;
+            }
+            for &neighbor in &self.nodes[current.slot as usize].links[layer] {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+                let dist = self.distance_to(query, neighbor);
+                let worst = results.peek().map_or(f32::INFINITY, |n: &Near| n.dist);
+                if results.len() < ef || dist < worst {
+                    let near = Near {
+                        dist,
+                        slot: neighbor,
+                    };
+                    candidates.push(Reverse(near));
+                    results.push(near);
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+        results.into_sorted_vec()
+    }
+
+    fn rebuild(&mut self) {
+        let nodes = std::mem::take(&mut self.nodes);
+        self.slots.clear();
+        self.entry = None;
+        self.deleted = 0;
+        for node in nodes.into_iter().filter(|node| !node.deleted) {
+            self.insert(node.id, node.vector);
+        }
+    }
+
+    /// Writes the index to a temporary file first, so a crash never leaves a torn index behind.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        let write = || -> io::Result<()> {
+            let mut out = BufWriter::new(fs::File::create(&tmp)?);
+            out.write_all(MAGIC)?;
+            for value in [
+                VERSION,
+                self.dim as u32,
+                self.metric.raw(),
+                self.m as u32,
+                self.ef_construction as u32,
+                self.entry.unwrap_or(NO_ENTRY),
+                self.nodes.len() as u32,
+            ] {
+                out.write_all(&value.to_le_bytes())?;
+            }
+            for node in &self.nodes {
+                out.write_all(&node.id.to_le_bytes())?;
+                out.write_all(&[node.deleted as u8])?;
+                out.write_all(&(node.links.len() as u32).to_le_bytes())?;
+                for x in &node.vector {
+                    out.write_all(&x.to_le_bytes())?;
+                }
+                for links in &node.links {
+                    out.write_all(&(links.len() as u32).to_le_bytes())?;
+                    for slot in links {
+                        out.write_all(&slot.to_le_bytes())?;
+                    }
+                }
+            }
+            out.into_inner()?.sync_all()?;
+            fs::rename(&tmp, path)
+        };
+        write().map_err(|e| Error::io(e, format_args!("cannot write {}", path.display())))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let bytes = fs::read(path)
+            .map_err(|e| Error::io(e, format_args!("cannot read {}", path.display())))?;
+        Self::from_bytes(&bytes).map_err(|message| {
+            Error::new(
+                ErrorCode::JsonParse,
+                format!("invalid index {}: {message}", path.display()),
+            )
+        })
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut input = Input(bytes);
+        if input.take(4)? != MAGIC {
+            return Err("not an index file".to_string());
+        }
+        let version = input.u32()?;
+        if version != VERSION {
+            return Err(format!("unsupported version {version}"));
+        }
+        let dim = input.u32()? as usize;
+        let metric = Metric::from_raw(input.u32()?).map_err(|e| e.message)?;
+        let m = input.u32()? as usize;
+        let ef_construction = input.u32()? as usize;
+        let entry = input.u32()?;
+        let count = input.u32()?;
+        let mut index = Hnsw::new(dim, metric, m, ef_construction).map_err(|e| e.message)?;
+        // Id, deleted flag, level count, vector and at least one link count per node.
+        let min_node_len = 8 + 1 + 4 + dim * 4 + 4;
+        if count as usize > input.0.len() / min_node_len {
+            return Err(format!("{count} nodes do not fit in the file"));
+        }
+        for slot in 0..count {
+            let id = i64::from_le_bytes(input.take(8)?.try_into().unwrap());
+            let deleted = input.take(1)?[0] != 0;
+            let levels = input.u32()? as usize;
+            if levels == 0 || levels > MAX_LEVEL + 1 {
+                return Err(format!("node {slot} has {levels} levels"));
+            }
+            let vector = input
+                .take(dim * 4)?
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+                .collect();
+            let mut links = Vec::with_capacity(levels);
+            for _ in 0..levels {
+                let len = input.u32()?;
+                if len > count {
+                    return Err(format!("node {slot} has {len} links"));
+                }
+                let layer: Vec<u32> = input
+                    .take(len as usize * 4)?
+                    .chunks_exact(4)
+                    .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                    .collect();
+                if layer.iter().any(|&s| s >= count) {
+                    return Err(format!("node {slot} links outside the index"));
+                }
+                links.push(layer);
+            }
+            if deleted {
+                index.deleted += 1;
+            } else if index.slots.insert(id, slot).is_some() {
+                return Err(format!("message {id} is indexed twice"));
+            }
+            index.nodes.push(Node {
+                id,
+                vector,
+                links,
+                deleted,
+            });
+        }
+        index.entry = match entry {
+            NO_ENTRY if count == 0 => None,
+            entry if entry < count => Some(entry),
+            _ => return Err("invalid entry point".to_string()),
+        };
+        // Links may only point to nodes that exist on the same layer.
+        for node in &index.nodes {
+            for (layer, links) in node.links.iter().enumerate() {
+                if links
+                    .iter()
+                    .any(|&s| index.nodes[s as usize].links.len() <= layer)
+                {
+                    return Err("links skip a layer".to_string());
+                }
+            }
+        }
+        index.rng ^= u64::from(count);
+        Ok(index)
+    }
+}
+
+struct Input<'a>(&'a [u8]);
+
+impl<'a> Input<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.0.len() {
+            return Err("unexpected end of file".to_string());
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(seed: u64, dim: usize) -> Vec<f32> {
+        let mut state = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (0..dim)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+            })
+            .collect()
+    }
+
+    fn index(count: i64) -> Hnsw {
+        let mut index = Hnsw::new(32, Metric::Cosine, 8, 64).unwrap();
+        for id in 0..count {
+            index.add(id, &vector(id as u64, 32)).unwrap();
+        }
+        index
+    }
+
+    #[test]
+    fn finds_the_same_neighbours_as_brute_force() {
+        let index = index(500);
+        let mut hits = 0;
+        for q in 1000..1020 {
+            let query = vector(q, 32);
+            let mut exact: Vec<(i64, f32)> = (0..500)
+                .map(|id| (id, similarity::cosine(&query, &vector(id as u64, 32))))
+                .collect();
+            exact.sort_by(|a, b| b.1.total_cmp(&a.1));
+            let found = index.search(&query, 10, 64, None).unwrap();
+            hits += found
+                .iter()
+                .filter(|(id, _)| exact[..10].iter().any(|e| e.0 == *id))
+                .count();
+        }
+        assert!(hits >= 190, "recall@10 was {hits}/200");
+    }
+
+    #[test]
+    fn removes_updates_and_filters_by_threshold() {
+        let mut index = index(50);
+        let target = vector(7, 32);
+        assert_eq!(index.search(&target, 1, 32, None).unwrap()[0].0, 7);
+        assert!(index.remove(7));
+        assert!(!index.remove(7));
+        assert_ne!(index.search(&target, 1, 32, None).unwrap()[0].0, 7);
+
+        index.update(3, &target).unwrap();
+        let best = index.search(&target, 5, 32, Some(0.99)).unwrap();
+        assert_eq!(best.len(), 1);
+        assert_eq!(best[0].0, 3);
+        assert!(index.add(3, &target).is_err());
+        assert!(index.search(&target[..5], 1, 32, None).is_err());
+
+        for id in 0..50 {
+            index.remove(id);
+        }
+        assert_eq!(index.len(), 0);
+        assert!(index.search(&target, 1, 32, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let mut index = index(100);
+        index.remove(42);
+        let path = std::env::temp_dir().join(format!("hnsw-test-{}.bin", std::process::id()));
+        index.save(&path).unwrap();
+        let loaded = Hnsw::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((loaded.dim(), loaded.len()), (32, 99));
+        for q in 0..5 {
+            let query = vector(q + 500, 32);
+            assert_eq!(
+                loaded.search(&query, 5, 64, None).unwrap(),
+                index.search(&query, 5, 64, None).unwrap()
+            );
+        }
+        assert!(Hnsw::from_bytes(b"HNSW\x01\0\0\0").is_err());
+    }
+
+    #[test]
+    fn rejects_implausible_headers_and_reports_write_errors() {
+        let path = std::env::temp_dir().join(format!("hnsw-header-{}.bin", std::process::id()));
+        index(3).save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(Hnsw::from_bytes(&bytes).is_ok());
+        // dim, m and node count.
+        for field in [8, 16, 28] {
+            let mut corrupt = bytes.clone();
+            corrupt[field..field + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(Hnsw::from_bytes(&corrupt).is_err());
+        }
+
+        let inside_file = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml/index.bin");
+        assert_eq!(index(1).save(inside_file).unwrap_err().code, ErrorCode::Io);
+        assert_eq!(
+            Hnsw::load("/definitely/missing/index.bin")
+                .err()
+                .unwrap()
+                .code,
+            ErrorCode::FileNotFound
+        );
+    }
+}
@@ -1 +1,23 @@
-mod ffi; 
\ No newline at end of file
+mod batch;
+mod bm25;
+mod bpe;
+mod cache;
+mod chat_template;
+mod chunk;
+mod context;
+mod embed;
+mod encoding;
+mod error;
+mod ffi;
+mod fusion;
+mod hnsw;
+mod log;
+mod pooling;
+mod profile;
+mod quantize;
+mod registry;
+mod sentencepiece;
+mod similarity;
+mod stop;
+mod stream;
+mod tiktoken;
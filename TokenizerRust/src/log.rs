@@ -0,0 +1,79 @@
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{PoisonError, RwLock};
+
+/// Severity levels, numbered like .NET's `Microsoft.Extensions.Logging.LogLevel`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[allow(dead_code)] // Every level is part of the C ABI, even if the crate does not emit it yet.
+pub enum Level {
+    Trace = 0,
+    Debug = 1,
+    Information = 2,
+    Warning = 3,
+    Error = 4,
+    Critical = 5,
+    None = 6,
+}
+
+/// Receives a level and a NUL-terminated UTF-8 message that is only valid during the call.
+pub type Callback = extern "C" fn(level: i32, message: *const c_char);
+
+static CALLBACK: RwLock<Option<Callback>> = RwLock::new(None);
+static MIN_LEVEL: AtomicI32 = AtomicI32::new(Level::Information as i32);
+
+pub fn set_callback(callback: Option<Callback>) {
+    *CALLBACK.write().unwrap_or_else(PoisonError::into_inner) = callback;
+}
+
+pub fn set_min_level(level: i32) {
+    MIN_LEVEL.store(level, Ordering::Relaxed);
+}
+
+/// Formats and delivers a message only when a callback is registered and `level` passes the filter.
+pub fn log(level: Level, message: impl FnOnce() -> String) {
+    if (level as i32) < MIN_LEVEL.load(Ordering::Relaxed) || level == Level::None {
+        return;
+    }
+    let Some(callback) = *CALLBACK.read().unwrap_or_else(PoisonError::into_inner) else {
+        return;
+    };
+    if let Ok(message) = CString::new(message().replace('\0', " ")) {
+        callback(level as i32, message.as_ptr());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::sync::Mutex;
+
+    static RECEIVED: Mutex<Vec<(i32, String)>> = Mutex::new(Vec::new());
+
+    extern "C" fn record(level: i32, message: *const c_char) {
+        let message = unsafe { CStr::from_ptr(message) }
+            .to_string_lossy()
+            .into_owned();
+        RECEIVED.lock().unwrap().push((level, message));
+    }
+
+    #[test]
+    fn filters_by_level_and_stays_silent_without_callback() {
+        set_min_level(Level::Debug as i32);
+        log(Level::Information, || {
+            unreachable!("no callback is registered")
+        });
+
+        set_callback(Some(record));
+        log(Level::Trace, || "dropped".to_string());
+        log(Level::Debug, || "kept".to_string());
+        set_callback(None);
+        set_min_level(Level::Information as i32);
+
+        let received = RECEIVED.lock().unwrap();
+        assert!(received.contains(&(Level::Debug as i32, "kept".to_string())));
+        assert!(!received.iter().any(|(_, m)| m == "dropped"));
+    }
+}
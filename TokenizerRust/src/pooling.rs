@@ -0,0 +1,162 @@
+use crate::error::{Error, ErrorCode};
+
+pub const POOLING_MEAN: u32 = 0;
+pub const POOLING_CLS: u32 = 1;
+pub const POOLING_MAX: u32 = 2;
+pub const POOLING_WEIGHTED_MEAN: u32 = 3;
+pub const POOLING_LAST_TOKEN: u32 = 4;
+
+/// How token states are combined into one vector. Only tokens with a non-zero mask count,
+/// so every strategy works with left and right padding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pooling {
+    Mean,
+    /// The first token, `[CLS]` or `<s>` for BERT-style encoders.
+    Cls,
+    Max,
+    /// Mean weighted by position, so later tokens that have seen more context count more,
+    /// as in SGPT.
+    WeightedMean,
+    /// The last token, for decoder models whose final token has attended to the whole text.
+    LastToken,
+}
+
+impl Pooling {
+    pub fn from_raw(pooling: u32) -> Result<Self, Error> {
+        match pooling {
+            POOLING_MEAN => Ok(Pooling::Mean),
+            POOLING_CLS => Ok(Pooling::Cls),
+            POOLING_MAX => Ok(Pooling::Max),
+            POOLING_WEIGHTED_MEAN => Ok(Pooling::WeightedMean),
+            POOLING_LAST_TOKEN => Ok(Pooling::LastToken),
+            other => Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("unknown pooling {other}"),
+            )),
+        }
+    }
+}
+
+/// Pools `[batch, seq, dim]` states into `[batch, dim]`. Rows without any unmasked token stay zero.
+pub fn pool(
+    pooling: Pooling,
+    hidden: &[f32],
+    mask: &[i64],
+    batch: usize,
+    seq: usize,
+    dim: usize,
+) -> Vec<f32> {
+    debug_assert_eq!(hidden.len(), batch * seq * dim);
+    debug_assert_eq!(mask.len(), batch * seq);
+    let mut pooled = vec![0f32; batch * dim];
+    if seq == 0 || dim == 0 {
+        return pooled;
+    }
+    for ((out, states), mask) in pooled
+        .chunks_exact_mut(dim)
+        .zip(hidden.chunks_exact(seq * dim))
+        .zip(mask.chunks_exact(seq))
+    {
+        let mut tokens = states
+            .chunks_exact(dim)
+            .zip(mask)
+            .filter(|(_, &m)| m != 0)
+            .map(|(state, _)| state);
+        match pooling {
+            Pooling::Cls => {
+                if let Some(first) = tokens.next() {
+                    out.copy_from_slice(first);
+                }
+            }
+            Pooling::LastToken => {
+                if let Some(last) = tokens.next_back() {
+                    out.copy_from_slice(last);
+                }
+            }
+            Pooling::Max => {
+                if let Some(first) = tokens.next() {
+                    out.copy_from_slice(first);
+                    for state in tokens {
+                        for (o, &s) in out.iter_mut().zip(state) {
+                            *o = o.max(s);
+                        }
+                    }
+                }
+            }
+            Pooling::Mean | Pooling::WeightedMean => {
+                let mut total = 0f32;
+                for (position, state) in tokens.enumerate() {
+                    let weight = match pooling {
+                        Pooling::WeightedMean => (position + 1) as f32,
+                        _ => 1.0,
+                    };
+                    for (o, s) in out.iter_mut().zip(state) {
+                        *o += weight * s;
+                    }
+                    total += weight;
+                }
+                if total > 0.0 {
+                    out.iter_mut().for_each(|o| *o /= total);
+                }
+            }
+        }
+    }
+    pooled
+}
+
+/// Keeps the first `dimensions` values of every `dim`-sized row. Matryoshka models are trained
+/// so that such prefixes are embeddings too, once they are normalized again.
+pub fn truncate(vectors: &[f32], dim: usize, dimensions: usize) -> Vec<f32> {
+    vectors
+        .chunks_exact(dim)
+        .flat_map(|row| &row[..dimensions.min(dim)])
+        .copied()
+        .collect()
+}
+
+/// Scales every `dim`-sized row of `vectors` to unit length, leaving zero rows as they are.
+pub fn normalize(vectors: &mut [f32], dim: usize) {
+    for row in vectors.chunks_exact_mut(dim) {
+        let norm = crate::similarity::dot(row, row).sqrt();
+        if norm > 0.0 {
+            row.iter_mut().for_each(|x| *x /= norm);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two rows of three 2-dimensional tokens; the first is right-padded, the second left-padded.
+    const HIDDEN: [f32; 12] = [
+        1.0, 2.0, 3.0, 0.0, 100.0, 100.0, 100.0, 100.0, 5.0, 6.0, -1.0, 8.0,
+    ];
+    const MASK: [i64; 6] = [1, 1, 0, 0, 1, 1];
+
+    #[test]
+    fn pools_only_unmasked_tokens() {
+        let pool = |pooling| pool(pooling, &HIDDEN, &MASK, 2, 3, 2);
+        assert_eq!(pool(Pooling::Mean), [2.0, 1.0, 2.0, 7.0]);
+        assert_eq!(pool(Pooling::Cls), [1.0, 2.0, 5.0, 6.0]);
+        assert_eq!(pool(Pooling::LastToken), [3.0, 0.0, -1.0, 8.0]);
+        assert_eq!(pool(Pooling::Max), [3.0, 2.0, 5.0, 8.0]);
+        let weighted = pool(Pooling::WeightedMean);
+        assert_eq!(weighted, [7.0 / 3.0, 2.0 / 3.0, 1.0, 22.0 / 3.0]);
+        assert_eq!(
+            super::pool(Pooling::Max, &[1.0, 1.0], &[0], 1, 1, 2),
+            [0.0; 2]
+        );
+        assert!(Pooling::from_raw(9).is_err());
+    }
+
+    #[test]
+    fn truncates_and_renormalizes_matryoshka_prefixes() {
+        let vectors = [3.0, 4.0, 12.0, 0.0, 1.0, 0.0];
+        let mut truncated = truncate(&vectors, 3, 2);
+        assert_eq!(truncated, [3.0, 4.0, 0.0, 1.0]);
+        normalize(&mut truncated, 2);
+        assert_eq!(truncated, [0.6, 0.8, 0.0, 1.0]);
+        assert_eq!(truncate(&vectors, 3, 5), vectors);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::embed::{EmbedderOptions, DEFAULT_MAX_LENGTH};
+use crate::error::{Error, ErrorCode};
+use crate::pooling;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::path::Path;
+
+/// Manifest file looked up next to `tokenizer.json` and the model.
+pub const PROFILE_FILE: &str = "embedding_profile.json";
+const TEXT_PLACEHOLDER: &str = "{text}";
+
+pub const TEXT_RAW: u32 = 0;
+pub const TEXT_QUERY: u32 = 1;
+pub const TEXT_DOCUMENT: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextKind {
+    /// Embedded as given, without an instruction.
+    Raw,
+    Query,
+    Document,
+}
+
+impl TextKind {
+    pub fn from_raw(kind: u32) -> Result<Self, Error> {
+        match kind {
+            TEXT_RAW => Ok(TextKind::Raw),
+            TEXT_QUERY => Ok(TextKind::Query),
+            TEXT_DOCUMENT => Ok(TextKind::Document),
+            other => Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("unknown text kind {other}"),
+            )),
+        }
+    }
+}
+
+/// How an embedding model expects to be fed, e.g. for multilingual-e5:
+///
+/// ```json
+/// {
+///   "version": "multilingual-e5-small/1",
+///   "query_template": "query: {text}",
+///   "document_template": "passage: {text}",
+///   "max_length": 512,
+///   "pooling": "mean",
+///   "normalize": true
+/// }
+/// ```
+///
+/// A template without `{text}` is a prefix. `pooling` is one of `mean`, `cls`, `max`,
+/// `weighted_mean` and `last_token`; `dimensions` optionally truncates Matryoshka embeddings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    /// Recorded with stored embeddings, so vectors from different models are never compared.
+    pub version: String,
+    pub query_template: Option<String>,
+    pub document_template: Option<String>,
+    pub options: EmbedderOptions,
+}
+
+impl Profile {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)
+            .map_err(|e| Error::io(e, format_args!("cannot read {}", path.display())))?;
+        let manifest = serde_json::from_slice(&bytes).map_err(|e| {
+            Error::new(
+                ErrorCode::JsonParse,
+                format!("cannot parse {}: {e}", path.display()),
+            )
+        })?;
+        Self::from_manifest(&manifest)
+            .map_err(|e| Error::new(e.code, format!("{}: {}", path.display(), e.message)))
+    }
+
+    /// Loads the profile from the directory holding `path`, such as `tokenizer.json`.
+    pub fn next_to(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let dir = path.as_ref().parent().unwrap_or(Path::new(""));
+        Self::from_file(dir.join(PROFILE_FILE))
+    }
+
+    pub fn from_manifest(manifest: &Value) -> Result<Self, Error> {
+        let invalid = |key: &str, expected: &str| {
+            Error::new(ErrorCode::JsonParse, format!("{key} must be {expected}"))
+        };
+        let string = |key: &str| match &manifest[key] {
+            Value::Null => Ok(None),
+            Value::String(s) => Ok(Some(s.clone())),
+            _ => Err(invalid(key, "a string")),
+        };
+        let count = |key: &str| match &manifest[key] {
+            Value::Null => Ok(0),
+            value => value
+                .as_u64()
+                .map(|n| n as usize)
+                .ok_or_else(|| invalid(key, "a non-negative integer")),
+        };
+        let version = string("version")?
+            .filter(|v| !v.is_empty())
+            .ok_or_else(|| invalid("version", "a non-empty string"))?;
+        let pooling = match string("pooling")?.as_deref() {
+            None | Some("mean") => pooling::POOLING_MEAN,
+            Some("cls") => pooling::POOLING_CLS,
+            Some("max") => pooling::POOLING_MAX,
+            Some("weighted_mean") => pooling::POOLING_WEIGHTED_MEAN,
+            Some("last_token") => pooling::POOLING_LAST_TOKEN,
+            Some(other) => {
+                return Err(Error::new(
+                    ErrorCode::JsonParse,
+                    format!("unknown pooling {other}"),
+                ))
+            }
+        };
+        let normalize = match &manifest["normalize"] {
+            Value::Null => true,
+            value => value
+                .as_bool()
+                .ok_or_else(|| invalid("normalize", "a boolean"))?,
+        };
+        Ok(Profile {
+            version,
+            query_template: string("query_template")?,
+            document_template: string("document_template")?,
+            options: EmbedderOptions {
+                max_length: match count("max_length")? {
+                    0 => DEFAULT_MAX_LENGTH,
+                    max_length => max_length,
+                },
+                pooling,
+                dimensions: count("dimensions")?,
+                normalize,
+            },
+        })
+    }
+
+    /// Applies the query or document template to `text`, unless it is already applied.
+    pub fn prepare<'a>(&self, kind: TextKind, text: &'a str) -> Cow<'a, str> {
+        let template = match kind {
+            TextKind::Raw => None,
+            TextKind::Query => self.query_template.as_deref(),
+            TextKind::Document => self.document_template.as_deref(),
+        };
+        let Some(template) = template else {
+            return Cow::Borrowed(text);
+        };
+        let (prefix, suffix) = template
+            .split_once(TEXT_PLACEHOLDER)
+            .unwrap_or((template, ""));
+        if text.starts_with(prefix) && text.ends_with(suffix) {
+            Cow::Borrowed(text)
+        } else {
+            Cow::Owned(format!("{prefix}{text}{suffix}"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn applies_templates_once() {
+        let profile = Profile::from_manifest(&json!({
+            "version": "e5/1",
+            "query_template": "query: ",
+            "document_template": "<doc>{text}</doc>",
+        }))
+        .unwrap();
+        assert_eq!(profile.prepare(TextKind::Query, "kot"), "query: kot");
+        assert_eq!(profile.prepare(TextKind::Query, "query: kot"), "query: kot");
+        assert_eq!(profile.prepare(TextKind::Document, "kot"), "<doc>kot</doc>");
+        assert_eq!(
+            profile.prepare(TextKind::Document, "<doc>kot</doc>"),
+            "<doc>kot</doc>"
+        );
+        assert_eq!(profile.prepare(TextKind::Raw, "kot"), "kot");
+        assert_eq!(profile.options.max_length, DEFAULT_MAX_LENGTH);
+        assert!(profile.options.normalize);
+    }
+
+    #[test]
+    fn loads_the_manifest_next_to_the_tokenizer() {
+        let tokenizer = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tokenizer.json");
+        let profile = Profile::next_to(tokenizer).unwrap();
+        assert_eq!(profile.version, "tiny-encoder/1");
+        assert_eq!(profile.options.pooling, pooling::POOLING_CLS);
+        assert_eq!(profile.options.max_length, 8);
+
+        let err = Profile::from_manifest(&json!({"version": "x", "pooling": "sum"}));
+        assert_eq!(err.unwrap_err().code, ErrorCode::JsonParse);
+        assert!(Profile::from_manifest(&json!({"pooling": "mean"})).is_err());
+    }
+}
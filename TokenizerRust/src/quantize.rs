@@ -0,0 +1,195 @@
+use crate::error::{Error, ErrorCode};
+use crate::similarity;
+
+/// Symmetric int8 quantization with one scale per vector: `x ≈ code * scale`.
+pub fn int8(vector: &[f32]) -> (Vec<i8>, f32) {
+    let max = vector.iter().fold(0f32, |max, x| max.max(x.abs()));
+    if max == 0.0 || !max.is_finite() {
+        return (vec![0; vector.len()], 0.0);
+    }
+    let scale = max / 127.0;
+    let codes = vector
+        .iter()
+        .map(|x| (x / scale).round().clamp(-127.0, 127.0) as i8)
+        .collect();
+    (codes, scale)
+}
+
+pub fn dequantize_int8(codes: &[i8], scale: f32) -> Vec<f32> {
+    codes.iter().map(|&c| c as f32 * scale).collect()
+}
+
+/// One bit per dimension, set for positive values, packed least significant bit first.
+pub fn binary(vector: &[f32]) -> Vec<u8> {
+    vector
+        .chunks(8)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (bit, &x)| byte | (((x > 0.0) as u8) << bit))
+        })
+        .collect()
+}
+
+pub fn binary_len(dim: usize) -> usize {
+    dim.div_ceil(8)
+}
+
+pub fn hamming(a: &[u8], b: &[u8]) -> u32 {
+    let (a_words, b_words) = (a.chunks_exact(8), b.chunks_exact(8));
+    let tail: u32 = a_words
+        .remainder()
+        .iter()
+        .zip(b_words.remainder())
+        .map(|(x, y)| (x ^ y).count_ones())
+        .sum();
+    a_words
+        .zip(b_words)
+        .map(|(x, y)| {
+            let x = u64::from_le_bytes(x.try_into().unwrap());
+            let y = u64::from_le_bytes(y.try_into().unwrap());
+            (x ^ y).count_ones()
+        })
+        .sum::<u32>()
+        + tail
+}
+
+pub fn int8_dot(a: &[i8], b: &[i8]) -> i32 {
+    a.iter().zip(b).map(|(&x, &y)| x as i32 * y as i32).sum()
+}
+
+fn rows<T>(rows: &[T], width: usize, what: &str) -> Result<usize, Error> {
+    if width == 0 || !rows.len().is_multiple_of(width) {
+        return Err(Error::new(
+            ErrorCode::InvalidArgument,
+            format!(
+                "{} {what} are not a whole number of rows of {width}",
+                rows.len()
+            ),
+        ));
+    }
+    Ok(rows.len() / width)
+}
+
+/// Nearest binary codes by Hamming distance, as `(row, distance)`, nearest first.
+pub fn hamming_top_k(query: &[u8], codes: &[u8], k: usize) -> Result<Vec<(usize, u32)>, Error> {
+    rows(codes, query.len(), "bytes")?;
+    let distances = codes
+        .chunks_exact(query.len())
+        .map(|row| hamming(query, row) as f32)
+        .enumerate();
+    Ok(similarity::best_k(distances, k, true)
+        .into_iter()
+        .map(|(row, distance)| (row, distance as u32))
+        .collect())
+}
+
+/// Best rows by approximate dot product of int8 codes, as `(row, score)`, best first.
+pub fn int8_top_k(
+    query: &[i8],
+    query_scale: f32,
+    codes: &[i8],
+    scales: &[f32],
+    k: usize,
+) -> Result<Vec<(usize, f32)>, Error> {
+    check_scales(rows(codes, query.len(), "codes")?, scales)?;
+    let scores = codes
+        .chunks_exact(query.len())
+        .zip(scales)
+        .map(|(row, &scale)| int8_dot(query, row) as f32 * query_scale * scale)
+        .enumerate();
+    Ok(similarity::best_k(scores, k, false))
+}
+
+/// Re-ranks candidate rows by the dot product of the float query with their int8 codes,
+/// recovering most of the precision lost by coarse binary or int8 search.
+pub fn rescore(
+    query: &[f32],
+    candidates: &[usize],
+    codes: &[i8],
+    scales: &[f32],
+    k: usize,
+) -> Result<Vec<(usize, f32)>, Error> {
+    let count = rows(codes, query.len(), "codes")?;
+    check_scales(count, scales)?;
+    if let Some(&row) = candidates.iter().find(|&&row| row >= count) {
+        return Err(Error::new(
+            ErrorCode::InvalidArgument,
+            format!("candidate {row} is outside {count} rows"),
+        ));
+    }
+    let scores = candidates.iter().map(|&row| {
+        let codes = &codes[row * query.len()..(row + 1) * query.len()];
+        let dot: f32 = query.iter().zip(codes).map(|(&q, &c)| q * c as f32).sum();
+        (row, dot * scales[row])
+    });
+    Ok(similarity::best_k(scores, k, false))
+}
+
+fn check_scales(rows: usize, scales: &[f32]) -> Result<(), Error> {
+    if scales.len() != rows {
+        return Err(Error::new(
+            ErrorCode::InvalidArgument,
+            format!("{} scales for {rows} rows", scales.len()),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(seed: usize, dim: usize) -> Vec<f32> {
+        let mut state = (seed as u64).wrapping_mul(2862933555777941757) ^ 3037000493;
+        let v: Vec<f32> = (0..dim)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (state >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+            })
+            .collect();
+        let norm = similarity::dot(&v, &v).sqrt();
+        v.iter().map(|x| x / norm).collect()
+    }
+
+    #[test]
+    fn int8_round_trip_stays_within_half_a_step() {
+        let v = vector(3, 100);
+        let (codes, scale) = int8(&v);
+        for (x, y) in v.iter().zip(dequantize_int8(&codes, scale)) {
+            assert!((x - y).abs() <= scale / 2.0 + 1e-6);
+        }
+        assert_eq!(int8(&[0.0; 4]), (vec![0; 4], 0.0));
+    }
+
+    #[test]
+    fn binary_packs_sign_bits_lsb_first() {
+        let bits = binary(&[1.0, -1.0, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0, 2.0]);
+        assert_eq!(bits, [0b0000_0101, 0b0000_0001]);
+        assert_eq!(hamming(&bits, &[0, 0]), 3);
+        assert_eq!(binary_len(9), 2);
+    }
+
+    #[test]
+    fn coarse_search_then_rescore_finds_the_exact_best() {
+        let dim = 64;
+        let data: Vec<Vec<f32>> = (0..200).map(|i| vector(i, dim)).collect();
+        let query = data[123].iter().map(|x| x + 0.01).collect::<Vec<_>>();
+        let (codes, scales): (Vec<Vec<i8>>, Vec<f32>) = data.iter().map(|v| int8(v)).unzip();
+        let codes = codes.concat();
+        let bits = data.iter().flat_map(|v| binary(v)).collect::<Vec<_>>();
+
+        let coarse = hamming_top_k(&binary(&query), &bits, 20).unwrap();
+        assert!(coarse.iter().any(|&(row, _)| row == 123));
+        let candidates: Vec<usize> = coarse.iter().map(|c| c.0).collect();
+        let best = rescore(&query, &candidates, &codes, &scales, 1).unwrap();
+        assert_eq!(best[0].0, 123);
+
+        let (q, q_scale) = int8(&query);
+        let best = int8_top_k(&q, q_scale, &codes, &scales, 3).unwrap();
+        let exact = similarity::dot(&query, &data[best[0].0]);
+        assert!((best[0].1 - exact).abs() / exact.abs() < 0.02);
+        assert!(rescore(&query, &[200], &codes, &scales, 1).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use crate::encoding;
+use crate::error::Error;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+use tokenizers::Tokenizer;
+
+/// Opaque handle returned to the caller. `0` is never issued and marks failure.
+pub type Handle = u64;
+
+pub const INVALID_HANDLE: Handle = 0;
+
+// Shared by every registry, so a handle of one kind is never mistaken for another.
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
+static TOKENIZERS: Registry<Tokenizer> = Registry::new();
+// Handle used by the legacy `tokenizer_init`/`tokenizer_encode`/`tokenizer_decode` API.
+static DEFAULT_HANDLE: AtomicU64 = AtomicU64::new(INVALID_HANDLE);
+// Bumped whenever a tokenizer is swapped in, so callers can invalidate cached token counts.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Thread-safe handle table. Lookups hand out an `Arc`, so a concurrent `remove`
+/// only drops the value once every in-flight call has finished.
+pub struct Registry<T> {
+    entries: RwLock<BTreeMap<Handle, Arc<T>>>,
+}
+
+impl<T> Registry<T> {
+    pub const fn new() -> Self {
+        Registry {
+            entries: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn insert(&self, value: T) -> Handle {
+        let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+        self.entries
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(handle, Arc::new(value));
+        handle
+    }
+
+    pub fn get(&self, handle: Handle) -> Option<Arc<T>> {
+        self.entries
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&handle)
+            .cloned()
+    }
+
+    /// Swaps the value behind an existing handle. Calls already holding the old `Arc`
+    /// finish on it; later lookups see the new value.
+    pub fn replace(&self, handle: Handle, value: T) -> bool {
+        match self
+            .entries
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_mut(&handle)
+        {
+            Some(entry) => {
+                *entry = Arc::new(value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&self, handle: Handle) -> bool {
+        self.entries
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&handle)
+            .is_some()
+    }
+
+    pub fn clear(&self) {
+        self.entries
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+pub fn insert(tokenizer: Tokenizer) -> Result<Handle, Error> {
+    Ok(TOKENIZERS.insert(encoding::prepare(tokenizer)?))
+}
+
+pub fn get(handle: Handle) -> Option<Arc<Tokenizer>> {
+    TOKENIZERS.get(handle)
+}
+
+pub fn replace(handle: Handle, tokenizer: Tokenizer) -> Result<bool, Error> {
+    let replaced = TOKENIZERS.replace(handle, encoding::prepare(tokenizer)?);
+    if replaced {
+        GENERATION.fetch_add(1, Ordering::AcqRel);
+    }
+    Ok(replaced)
+}
+
+pub fn remove(handle: Handle) -> bool {
+    let removed = TOKENIZERS.remove(handle);
+    if DEFAULT_HANDLE
+        .compare_exchange(handle, INVALID_HANDLE, Ordering::AcqRel, Ordering::Relaxed)
+        .is_ok()
+    {
+        GENERATION.fetch_add(1, Ordering::AcqRel);
+    }
+    removed
+}
+
+pub fn clear() {
+    if DEFAULT_HANDLE.swap(INVALID_HANDLE, Ordering::AcqRel) != INVALID_HANDLE {
+        GENERATION.fetch_add(1, Ordering::AcqRel);
+    }
+    TOKENIZERS.clear();
+}
+
+/// Makes `handle` the default tokenizer and returns the one it replaced.
+pub fn set_default(handle: Handle) -> Handle {
+    let previous = DEFAULT_HANDLE.swap(handle, Ordering::AcqRel);
+    GENERATION.fetch_add(1, Ordering::AcqRel);
+    previous
+}
+
+pub fn default_handle() -> Handle {
+    DEFAULT_HANDLE.load(Ordering::Acquire)
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Acquire)
+}
@@ -0,0 +1,324 @@
+use crate::bpe;
+use crate::error::{Error, ErrorCode};
+use std::collections::HashMap;
+use tokenizers::decoders::byte_fallback::ByteFallback;
+use tokenizers::decoders::fuse::Fuse;
+use tokenizers::decoders::sequence::Sequence as DecoderSequence;
+use tokenizers::decoders::strip::Strip as StripDecoder;
+use tokenizers::models::bpe::BPE;
+use tokenizers::models::unigram::Unigram;
+use tokenizers::normalizers::replace::ReplacePattern;
+use tokenizers::normalizers::{Precompiled, Prepend, Replace, Sequence, Strip};
+use tokenizers::pre_tokenizers::split::Split;
+use tokenizers::processors::template::TemplateProcessing;
+use tokenizers::{AddedToken, ModelWrapper, SplitDelimiterBehavior, Tokenizer};
+
+const SPACE: &str = "▁";
+
+// `ModelProto.TrainerSpec.ModelType` and `ModelProto.SentencePiece.Type`.
+const UNIGRAM: u64 = 1;
+const BPE_MODEL: u64 = 2;
+const NORMAL: u64 = 1;
+const UNKNOWN: u64 = 2;
+const CONTROL: u64 = 3;
+const USER_DEFINED: u64 = 4;
+
+/// The parts of SentencePiece's `ModelProto` needed to rebuild the tokenizer, with proto defaults.
+struct ModelProto {
+    pieces: Vec<Piece>,
+    model_type: u64,
+    byte_fallback: bool,
+    split_by_whitespace: bool,
+    unk_id: usize,
+    bos_id: i32,
+    precompiled_charsmap: Vec<u8>,
+    add_dummy_prefix: bool,
+    remove_extra_whitespaces: bool,
+    escape_whitespaces: bool,
+}
+
+struct Piece {
+    piece: String,
+    score: f32,
+    kind: u64,
+}
+
+/// Builds a tokenizer from a SentencePiece `tokenizer.model` protobuf, the way
+/// `transformers` converts Llama and Gemma models to `tokenizer.json`.
+pub fn from_bytes(bytes: &[u8]) -> Result<Tokenizer, Error> {
+    let proto = parse(bytes).map_err(|e| {
+        Error::new(
+            ErrorCode::JsonParse,
+            format!("invalid SentencePiece model: {e}"),
+        )
+    })?;
+    build(proto)
+}
+
+fn build(proto: ModelProto) -> Result<Tokenizer, Error> {
+    let unk = proto
+        .pieces
+        .get(proto.unk_id)
+        .ok_or_else(|| Error::new(ErrorCode::JsonParse, "unk_id is outside the vocabulary"))?;
+    let model: ModelWrapper = match proto.model_type {
+        UNIGRAM => {
+            let vocab = proto
+                .pieces
+                .iter()
+                .map(|p| (p.piece.clone(), p.score as f64))
+                .collect();
+            Unigram::from(vocab, Some(proto.unk_id), proto.byte_fallback)?.into()
+        }
+        BPE_MODEL => {
+            let vocab: HashMap<String, u32> = proto
+                .pieces
+                .iter()
+                .enumerate()
+                .map(|(id, p)| (p.piece.clone(), id as u32))
+                .collect();
+            let merges = bpe::merges_by_rank(&vocab);
+            BPE::builder()
+                .vocab_and_merges(vocab, merges)
+                .unk_token(unk.piece.clone())
+                .fuse_unk(true)
+                .byte_fallback(proto.byte_fallback)
+                .build()?
+                .into()
+        }
+        other => {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("SentencePiece model type {other} is not supported"),
+            ))
+        }
+    };
+
+    let mut tokenizer = Tokenizer::new(model);
+    let mut normalizers = Vec::new();
+    if !proto.precompiled_charsmap.is_empty() {
+        let precompiled = Precompiled::from(&proto.precompiled_charsmap)
+            .map_err(|e| Error::new(ErrorCode::JsonParse, e.to_string()))?;
+        normalizers.push(precompiled.into());
+    }
+    if proto.remove_extra_whitespaces {
+        normalizers.push(Strip::new(true, true).into());
+        normalizers.push(Replace::new(ReplacePattern::Regex(" {2,}".into()), " ")?.into());
+    }
+    if proto.add_dummy_prefix {
+        normalizers.push(Prepend::new(SPACE.to_string()).into());
+    }
+    if proto.escape_whitespaces {
+        normalizers.push(Replace::new(" ", SPACE)?.into());
+    }
+    tokenizer.with_normalizer(Sequence::new(normalizers));
+    if proto.split_by_whitespace {
+        tokenizer.with_pre_tokenizer(Split::new(
+            SPACE,
+            SplitDelimiterBehavior::MergedWithNext,
+            false,
+        )?);
+    }
+
+    let mut decoders = vec![
+        Replace::new(SPACE, " ")?.into(),
+        ByteFallback::new().into(),
+        Fuse::new().into(),
+    ];
+    if proto.add_dummy_prefix {
+        decoders.push(StripDecoder::new(' ', 1, 0).into());
+    }
+    tokenizer.with_decoder(DecoderSequence::new(decoders));
+
+    if let Some(bos) = usize::try_from(proto.bos_id)
+        .ok()
+        .and_then(|id| proto.pieces.get(id))
+    {
+        let processor = TemplateProcessing::builder()
+            .try_single(format!("{} $A", bos.piece))
+            .and_then(|b| b.try_pair(format!("{0} $A {0} $B", bos.piece)))
+            .map_err(|e| Error::new(ErrorCode::JsonParse, e))?
+            .special_tokens(vec![(bos.piece.clone(), proto.bos_id as u32)])
+            .build()
+            .map_err(|e| Error::new(ErrorCode::JsonParse, e.to_string()))?;
+        tokenizer.with_post_processor(processor);
+    }
+
+    let added: Vec<_> = proto
+        .pieces
+        .iter()
+        .filter(|p| matches!(p.kind, UNKNOWN | CONTROL | USER_DEFINED))
+        .map(|p| AddedToken::from(p.piece.clone(), p.kind != USER_DEFINED).normalized(false))
+        .collect();
+    tokenizer.add_tokens(&added);
+    Ok(tokenizer)
+}
+
+fn parse(bytes: &[u8]) -> Result<ModelProto, String> {
+    let mut proto = ModelProto {
+        pieces: Vec::new(),
+        model_type: UNIGRAM,
+        byte_fallback: false,
+        split_by_whitespace: true,
+        unk_id: 0,
+        bos_id: 1,
+        precompiled_charsmap: Vec::new(),
+        add_dummy_prefix: true,
+        remove_extra_whitespaces: true,
+        escape_whitespaces: true,
+    };
+    let mut reader = Reader(bytes);
+    while let Some((field, value)) = reader.field()? {
+        match (field, value) {
+            (1, Value::Bytes(piece)) => proto.pieces.push(parse_piece(piece)?),
+            (2, Value::Bytes(trainer_spec)) => {
+                let mut reader = Reader(trainer_spec);
+                while let Some((field, value)) = reader.field()? {
+                    match (field, value) {
+                        (3, Value::Varint(v)) => proto.model_type = v,
+                        (22, Value::Varint(v)) => proto.split_by_whitespace = v != 0,
+                        (35, Value::Varint(v)) => proto.byte_fallback = v != 0,
+                        (40, Value::Varint(v)) => proto.unk_id = v as usize,
+                        (41, Value::Varint(v)) => proto.bos_id = v as i32,
+                        _ => {}
+                    }
+                }
+            }
+            (3, Value::Bytes(normalizer_spec)) => {
+                let mut reader = Reader(normalizer_spec);
+                while let Some((field, value)) = reader.field()? {
+                    match (field, value) {
+                        (2, Value::Bytes(charsmap)) => {
+                            proto.precompiled_charsmap = charsmap.to_vec()
+                        }
+                        (3, Value::Varint(v)) => proto.add_dummy_prefix = v != 0,
+                        (4, Value::Varint(v)) => proto.remove_extra_whitespaces = v != 0,
+                        (5, Value::Varint(v)) => proto.escape_whitespaces = v != 0,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if proto.pieces.is_empty() {
+        return Err("the model has no pieces".to_string());
+    }
+    Ok(proto)
+}
+
+fn parse_piece(bytes: &[u8]) -> Result<Piece, String> {
+    let mut piece = Piece {
+        piece: String::new(),
+        score: 0.0,
+        kind: NORMAL,
+    };
+    let mut reader = Reader(bytes);
+    while let Some((field, value)) = reader.field()? {
+        match (field, value) {
+            (1, Value::Bytes(s)) => {
+                piece.piece = String::from_utf8(s.to_vec()).map_err(|e| e.to_string())?
+            }
+            (2, Value::Fixed32(bits)) => piece.score = f32::from_bits(bits),
+            (3, Value::Varint(kind)) => piece.kind = kind,
+            _ => {}
+        }
+    }
+    Ok(piece)
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Fixed64,
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// Minimal protobuf wire-format reader.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn field(&mut self) -> Result<Option<(u64, Value<'a>)>, String> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Value::Fixed64
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => Value::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+            wire_type => return Err(format!("unsupported wire type {wire_type}")),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *self.take(1)?.first().unwrap();
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("varint is too long".to_string())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.0.len() {
+            return Err("unexpected end of data".to_string());
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/sentencepiece.model");
+
+    fn tokenizer() -> Tokenizer {
+        from_bytes(&std::fs::read(FIXTURE).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn encodes_like_sentencepiece_bpe() {
+        let tokenizer = tokenizer();
+        let encoding = tokenizer.encode("hello world", true).unwrap();
+        assert_eq!(encoding.get_tokens(), ["<s>", "▁hello", "▁world"]);
+        assert_eq!(
+            tokenizer.decode(encoding.get_ids(), true).unwrap(),
+            "hello world"
+        );
+    }
+
+    #[test]
+    fn falls_back_to_bytes_and_keeps_control_tokens() {
+        let tokenizer = tokenizer();
+        let encoding = tokenizer.encode("hello ż</s>", false).unwrap();
+        assert_eq!(
+            encoding.get_tokens(),
+            ["▁hello", "▁", "<0xC5>", "<0xBC>", "</s>"]
+        );
+        assert_eq!(
+            tokenizer.decode(encoding.get_ids(), true).unwrap(),
+            "hello ż"
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_protobuf() {
+        let bytes = std::fs::read(FIXTURE).unwrap();
+        let err = from_bytes(&bytes[..bytes.len() / 2]).err().unwrap();
+        assert_eq!(err.code, ErrorCode::JsonParse);
+    }
+}
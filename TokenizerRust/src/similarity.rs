@@ -0,0 +1,331 @@
+use crate::error::{Error, ErrorCode};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+pub const METRIC_COSINE: u32 = 0;
+pub const METRIC_DOT: u32 = 1;
+pub const METRIC_L2: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    Cosine,
+    Dot,
+    /// Euclidean distance; unlike the other metrics, lower is better.
+    L2,
+}
+
+impl Metric {
+    pub fn from_raw(metric: u32) -> Result<Self, Error> {
+        match metric {
+            METRIC_COSINE => Ok(Metric::Cosine),
+            METRIC_DOT => Ok(Metric::Dot),
+            METRIC_L2 => Ok(Metric::L2),
+            other => Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("unknown metric {other}"),
+            )),
+        }
+    }
+
+    pub fn raw(self) -> u32 {
+        match self {
+            Metric::Cosine => METRIC_COSINE,
+            Metric::Dot => METRIC_DOT,
+            Metric::L2 => METRIC_L2,
+        }
+    }
+
+    pub fn score(self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Metric::Cosine => cosine(a, b),
+            Metric::Dot => dot(a, b),
+            Metric::L2 => l2_squared(a, b).sqrt(),
+        }
+    }
+
+    pub fn lower_is_better(self) -> bool {
+        self == Metric::L2
+    }
+}
+
+/// Panics when the lengths differ: the SIMD kernels read `b` up to `a.len()`.
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    assert_eq!(a.len(), b.len());
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+        return unsafe { x86::dot(a, b) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    return unsafe { neon::dot(a, b) };
+    #[allow(unreachable_code)]
+    scalar::dot(a, b)
+}
+
+/// Panics when the lengths differ, like `dot`.
+pub fn l2_squared(a: &[f32], b: &[f32]) -> f32 {
+    assert_eq!(a.len(), b.len());
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+        return unsafe { x86::l2_squared(a, b) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    return unsafe { neon::l2_squared(a, b) };
+    #[allow(unreachable_code)]
+    scalar::l2_squared(a, b)
+}
+
+/// Cosine similarity; `0` when either vector has zero length.
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    cosine_with_norm(a, b, dot(a, a).sqrt())
+}
+
+fn cosine_with_norm(query: &[f32], row: &[f32], query_norm: f32) -> f32 {
+    let norm = query_norm * dot(row, row).sqrt();
+    if norm == 0.0 {
+        0.0
+    } else {
+        dot(query, row) / norm
+    }
+}
+
+/// Scores `query` against every `dim`-sized row of `rows` and returns the best `k`
+/// as `(row index, score)`, best first.
+pub fn top_k(
+    query: &[f32],
+    rows: &[f32],
+    metric: Metric,
+    k: usize,
+) -> Result<Vec<(usize, f32)>, Error> {
+    let dim = query.len();
+    if dim == 0 || !rows.len().is_multiple_of(dim) {
+        return Err(Error::new(
+            ErrorCode::InvalidArgument,
+            format!(
+                "{} values are not a whole number of {dim}-dimensional rows",
+                rows.len()
+            ),
+        ));
+    }
+    let query_norm = dot(query, query).sqrt();
+    let scores = rows.chunks_exact(dim).map(|row| match metric {
+        Metric::Cosine => cosine_with_norm(query, row, query_norm),
+        _ => metric.score(query, row),
+    });
+    Ok(best_k(scores.enumerate(), k, metric.lower_is_better()))
+}
+
+/// Keeps the best `k` of `(index, score)` pairs, best first; ties go to the lower index.
+pub fn best_k(
+    scores: impl IntoIterator<Item = (usize, f32)>,
+    k: usize,
+    lower_is_better: bool,
+) -> Vec<(usize, f32)> {
+    if k == 0 {
+        return Vec::new();
+    }
+    let scores = scores.into_iter();
+    // `k` comes straight from the caller, so never presize beyond the input.
+    let capacity = k.min(scores.size_hint().0).saturating_add(1);
+    // Min-heap of the best candidates so far: the root is the worst of them.
+    let mut heap = BinaryHeap::with_capacity(capacity);
+    for (index, score) in scores {
+        heap.push(Reverse(Candidate {
+            score,
+            index,
+            lower_is_better,
+        }));
+        if heap.len() > k {
+            heap.pop();
+        }
+    }
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(c)| (c.index, c.score))
+        .collect()
+}
+
+struct Candidate {
+    score: f32,
+    index: usize,
+    lower_is_better: bool,
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let by_score = if self.lower_is_better {
+            other.score.total_cmp(&self.score)
+        } else {
+            self.score.total_cmp(&other.score)
+        };
+        by_score.then(other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+mod scalar {
+    const LANES: usize = 8;
+
+    // Independent lanes let the compiler vectorize without reassociating a single sum.
+    pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+        let mut acc = [0.0f32; LANES];
+        let (a_chunks, b_chunks) = (a.chunks_exact(LANES), b.chunks_exact(LANES));
+        let tail: f32 = a_chunks
+            .remainder()
+            .iter()
+            .zip(b_chunks.remainder())
+            .map(|(x, y)| x * y)
+            .sum();
+        for (x, y) in a_chunks.zip(b_chunks) {
+            for i in 0..LANES {
+                acc[i] += x[i] * y[i];
+            }
+        }
+        acc.iter().sum::<f32>() + tail
+    }
+
+    pub fn l2_squared(a: &[f32], b: &[f32]) -> f32 {
+        let mut acc = [0.0f32; LANES];
+        let (a_chunks, b_chunks) = (a.chunks_exact(LANES), b.chunks_exact(LANES));
+        let tail: f32 = a_chunks
+            .remainder()
+            .iter()
+            .zip(b_chunks.remainder())
+            .map(|(x, y)| (x - y) * (x - y))
+            .sum();
+        for (x, y) in a_chunks.zip(b_chunks) {
+            for i in 0..LANES {
+                let d = x[i] - y[i];
+                acc[i] += d * d;
+            }
+        }
+        acc.iter().sum::<f32>() + tail
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn dot(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len() / 8 * 8;
+        let mut acc = _mm256_setzero_ps();
+        for i in (0..n).step_by(8) {
+            let x = _mm256_loadu_ps(a.as_ptr().add(i));
+            let y = _mm256_loadu_ps(b.as_ptr().add(i));
+            acc = _mm256_fmadd_ps(x, y, acc);
+        }
+        sum(acc) + super::scalar::dot(&a[n..], &b[n..])
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn l2_squared(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len() / 8 * 8;
+        let mut acc = _mm256_setzero_ps();
+        for i in (0..n).step_by(8) {
+            let d = _mm256_sub_ps(
+                _mm256_loadu_ps(a.as_ptr().add(i)),
+                _mm256_loadu_ps(b.as_ptr().add(i)),
+            );
+            acc = _mm256_fmadd_ps(d, d, acc);
+        }
+        sum(acc) + super::scalar::l2_squared(&a[n..], &b[n..])
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn sum(v: __m256) -> f32 {
+        let pairs = _mm_add_ps(_mm256_castps256_ps128(v), _mm256_extractf128_ps(v, 1));
+        let pairs = _mm_add_ps(pairs, _mm_movehl_ps(pairs, pairs));
+        _mm_cvtss_f32(_mm_add_ss(pairs, _mm_shuffle_ps(pairs, pairs, 1)))
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    pub unsafe fn dot(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len() / 4 * 4;
+        let mut acc = vdupq_n_f32(0.0);
+        for i in (0..n).step_by(4) {
+            acc = vfmaq_f32(
+                acc,
+                vld1q_f32(a.as_ptr().add(i)),
+                vld1q_f32(b.as_ptr().add(i)),
+            );
+        }
+        vaddvq_f32(acc) + super::scalar::dot(&a[n..], &b[n..])
+    }
+
+    pub unsafe fn l2_squared(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len() / 4 * 4;
+        let mut acc = vdupq_n_f32(0.0);
+        for i in (0..n).step_by(4) {
+            let d = vsubq_f32(vld1q_f32(a.as_ptr().add(i)), vld1q_f32(b.as_ptr().add(i)));
+            acc = vfmaq_f32(acc, d, d);
+        }
+        vaddvq_f32(acc) + super::scalar::l2_squared(&a[n..], &b[n..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(seed: u32, dim: usize) -> Vec<f32> {
+        (0..dim)
+            .map(|i| ((seed as usize * 31 + i * 17) % 23) as f32 / 11.0 - 1.0)
+            .collect()
+    }
+
+    #[test]
+    fn simd_kernels_match_scalar() {
+        for dim in [1, 7, 8, 13, 384] {
+            let (a, b) = (vector(1, dim), vector(2, dim));
+            let close = |x: f32, y: f32| (x - y).abs() <= 1e-4 * (1.0 + y.abs());
+            assert!(close(dot(&a, &b), scalar::dot(&a, &b)));
+            assert!(close(l2_squared(&a, &b), scalar::l2_squared(&a, &b)));
+        }
+    }
+
+    #[test]
+    fn metrics_agree_with_definitions() {
+        let (a, b) = ([1.0, 0.0, 0.0], [3.0, 4.0, 0.0]);
+        assert_eq!(dot(&a, &b), 3.0);
+        assert!((cosine(&a, &b) - 0.6).abs() < 1e-6);
+        assert!((Metric::L2.score(&a, &b) - 20f32.sqrt()).abs() < 1e-6);
+        assert_eq!(cosine(&a, &[0.0; 3]), 0.0);
+    }
+
+    #[test]
+    fn top_k_returns_best_rows_first() {
+        let query = [1.0, 0.0];
+        let rows = [0.0, 1.0, 1.0, 0.1, -1.0, 0.0, 2.0, 0.0];
+        let best = top_k(&query, &rows, Metric::Cosine, 2).unwrap();
+        assert_eq!(best.iter().map(|b| b.0).collect::<Vec<_>>(), [3, 1]);
+        let nearest = top_k(&query, &rows, Metric::L2, 3).unwrap();
+        assert_eq!(nearest.iter().map(|b| b.0).collect::<Vec<_>>(), [1, 3, 0]);
+        assert_eq!(top_k(&query, &rows, Metric::Dot, 10).unwrap().len(), 4);
+        assert!(top_k(&query, &rows[..3], Metric::Dot, 1).is_err());
+        assert!(top_k(&query, &rows, Metric::Dot, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn mismatched_lengths_panic() {
+        dot(&[1.0; 16], &[1.0; 3]);
+    }
+}
@@ -0,0 +1,182 @@
+use crate::error::{Error, ErrorCode};
+use aho_corasick::{AhoCorasick, MatchKind};
+use std::collections::HashSet;
+
+/// Detects stop sequences in generated output without rescanning the whole response.
+///
+/// Only the tail that could still be the beginning of a stop string is kept between calls,
+/// so each push costs time proportional to the new text plus the longest stop string.
+pub struct StopMatcher {
+    automaton: AhoCorasick,
+    stop_strings: Vec<String>,
+    stop_ids: HashSet<u32>,
+    tail: String,
+    stopped: Option<StopReason>,
+    /// Bytes and UTF-16 units withheld since the stop, counted instead of buffered.
+    withheld_after_stop: (usize, usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// Index into the stop strings the matcher was created with.
+    Text(usize),
+    Token(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StopStatus {
+    pub stopped: Option<StopReason>,
+    /// Trailing bytes of the pushed text that must not be shown: a possible partial stop
+    /// sequence while running, or the stop sequence and everything after it once stopped.
+    pub withheld_bytes: usize,
+    pub withheld_utf16: usize,
+}
+
+impl StopMatcher {
+    pub fn new(stop_strings: Vec<String>, stop_ids: HashSet<u32>) -> Result<Self, Error> {
+        if stop_strings.iter().any(String::is_empty) {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                "stop strings must not be empty",
+            ));
+        }
+        let automaton = AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostFirst)
+            .build(&stop_strings)
+            .map_err(|e| Error::new(ErrorCode::InvalidArgument, e.to_string()))?;
+        Ok(StopMatcher {
+            automaton,
+            stop_strings,
+            stop_ids,
+            tail: String::new(),
+            stopped: None,
+            withheld_after_stop: (0, 0),
+        })
+    }
+
+    pub fn push_text(&mut self, text: &str) -> StopStatus {
+        if self.stopped.is_some() {
+            self.withheld_after_stop.0 += text.len();
+            self.withheld_after_stop.1 += text.encode_utf16().count();
+            return self.status();
+        }
+        self.tail.push_str(text);
+        if let Some(found) = self.automaton.find(&self.tail) {
+            self.stopped = Some(StopReason::Text(found.pattern().as_usize()));
+            let withheld = &self.tail[found.start()..];
+            self.withheld_after_stop = (withheld.len(), withheld.encode_utf16().count());
+            self.tail.clear();
+            return self.status();
+        }
+        let partial = self.partial_suffix_len();
+        self.tail.drain(..self.tail.len() - partial);
+        self.status()
+    }
+
+    pub fn push_token(&mut self, id: u32) -> StopStatus {
+        if self.stopped.is_none() && self.stop_ids.contains(&id) {
+            self.stopped = Some(StopReason::Token(id));
+            // Whatever was held back turned out not to be a stop string.
+            self.tail.clear();
+        }
+        self.status()
+    }
+
+    pub fn reset(&mut self) {
+        self.tail.clear();
+        self.stopped = None;
+        self.withheld_after_stop = (0, 0);
+    }
+
+    fn status(&self) -> StopStatus {
+        StopStatus {
+            stopped: self.stopped,
+            withheld_bytes: self.tail.len() + self.withheld_after_stop.0,
+            withheld_utf16: self.tail.encode_utf16().count() + self.withheld_after_stop.1,
+        }
+    }
+
+    /// Length of the longest suffix of the tail that is a proper prefix of a stop string.
+    fn partial_suffix_len(&self) -> usize {
+        let longest = self.stop_strings.iter().map(String::len).max().unwrap_or(0);
+        let start = self.tail.len() - self.tail.len().min(longest.saturating_sub(1));
+        (start..self.tail.len())
+            .filter(|&i| self.tail.is_char_boundary(i))
+            .map(|i| &self.tail[i..])
+            .find(|suffix| self.stop_strings.iter().any(|s| s.starts_with(suffix)))
+            .map_or(0, str::len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher() -> StopMatcher {
+        let stops = ["<|im_end|>", "<|user|>", "\n\nUser:"];
+        StopMatcher::new(
+            stops.iter().map(|s| s.to_string()).collect(),
+            HashSet::from([32001]),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn withholds_possible_prefix_across_pushes() {
+        let mut m = matcher();
+        assert_eq!(m.push_text("Cześć").withheld_bytes, 0);
+        let status = m.push_text(" <|im");
+        assert_eq!(status.stopped, None);
+        assert_eq!(status.withheld_bytes, 4);
+        let status = m.push_text("_en");
+        assert_eq!(status.withheld_bytes, 7);
+        let status = m.push_text("d|> trailing");
+        assert_eq!(status.stopped, Some(StopReason::Text(0)));
+        assert_eq!(status.withheld_bytes, "<|im_end|> trailing".len());
+    }
+
+    #[test]
+    fn counts_text_after_a_stop_without_buffering_it() {
+        let mut m = matcher();
+        m.push_text("ok<|im_end|>");
+        let status = m.push_text(" więcej");
+        assert_eq!(status.withheld_bytes, "<|im_end|> więcej".len());
+        assert_eq!(status.withheld_utf16, 17);
+        assert!(m.tail.is_empty());
+    }
+
+    #[test]
+    fn releases_text_that_stops_looking_like_a_prefix() {
+        let mut m = matcher();
+        assert_eq!(m.push_text("a <|").withheld_bytes, 2);
+        let status = m.push_text("x");
+        assert_eq!(status.stopped, None);
+        assert_eq!(status.withheld_bytes, 0);
+    }
+
+    #[test]
+    fn counts_withheld_text_in_utf16_units() {
+        let mut m = StopMatcher::new(vec!["😀😀".to_string()], HashSet::new()).unwrap();
+        let status = m.push_text("ok😀");
+        assert_eq!(status.withheld_bytes, 4);
+        assert_eq!(status.withheld_utf16, 2);
+    }
+
+    #[test]
+    fn stops_on_special_token_and_releases_tail() {
+        let mut m = matcher();
+        m.push_text("Hi\n\n");
+        let status = m.push_token(32001);
+        assert_eq!(status.stopped, Some(StopReason::Token(32001)));
+        assert_eq!(status.withheld_bytes, 0);
+        assert_eq!(m.push_text("more").stopped, Some(StopReason::Token(32001)));
+
+        m.reset();
+        assert_eq!(m.push_text("<|user|>").stopped, Some(StopReason::Text(1)));
+    }
+
+    #[test]
+    fn rejects_empty_stop_string() {
+        assert!(StopMatcher::new(vec![String::new()], HashSet::new()).is_err());
+    }
+}
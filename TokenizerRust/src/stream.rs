@@ -0,0 +1,146 @@
+use crate::error::{Error, ErrorCode};
+use std::sync::Arc;
+use tokenizers::Tokenizer;
+
+const REPLACEMENT_CHAR: char = '\u{FFFD}';
+
+/// Incremental detokenizer for token-by-token generation, following `tokenizers`' `DecodeStream`.
+///
+/// Decoding single tokens in isolation splits multi-byte characters spread over byte-fallback
+/// tokens and drops the leading space of SentencePiece pieces. Instead, the decoder keeps a short
+/// window of recent ids and emits only the text that became final since the previous step.
+pub struct StreamDecoder {
+    tokenizer: Arc<Tokenizer>,
+    skip_special_tokens: bool,
+    ids: Vec<u32>,
+    prefix: String,
+    prefix_index: usize,
+}
+
+impl StreamDecoder {
+    pub fn new(tokenizer: Arc<Tokenizer>, skip_special_tokens: bool) -> Self {
+        StreamDecoder {
+            tokenizer,
+            skip_special_tokens,
+            ids: Vec::new(),
+            prefix: String::new(),
+            prefix_index: 0,
+        }
+    }
+
+    /// Feeds one id and returns the newly completed text, if any.
+    pub fn step(&mut self, id: u32) -> Result<Option<String>, Error> {
+        if self.prefix.is_empty() && !self.ids.is_empty() {
+            let prefix = self.decode(&self.ids)?;
+            if !prefix.ends_with(REPLACEMENT_CHAR) {
+                self.prefix = prefix;
+                self.prefix_index = self.ids.len();
+            }
+        }
+        self.ids.push(id);
+        let text = self.decode(&self.ids)?;
+        if text.len() <= self.prefix.len() || text.ends_with(REPLACEMENT_CHAR) {
+            return Ok(None);
+        }
+        let delta = text.strip_prefix(self.prefix.as_str()).ok_or_else(|| {
+            Error::new(
+                ErrorCode::DecodeFailed,
+                "decoded text no longer starts with the previous prefix",
+            )
+        })?;
+        let delta = delta.to_string();
+        let prefix_index = self.ids.len() - self.prefix_index;
+        self.ids.drain(..self.prefix_index);
+        self.prefix = self.decode(&self.ids)?;
+        self.prefix_index = prefix_index;
+        Ok(Some(delta))
+    }
+
+    pub fn reset(&mut self) {
+        self.ids.clear();
+        self.prefix.clear();
+        self.prefix_index = 0;
+    }
+
+    fn decode(&self, ids: &[u32]) -> Result<String, Error> {
+        self.tokenizer
+            .decode(ids, self.skip_special_tokens)
+            .map_err(|e| Error::new(ErrorCode::DecodeFailed, e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/byte_fallback.json");
+
+    fn tokenizer() -> Arc<Tokenizer> {
+        Arc::new(Tokenizer::from_file(FIXTURE).unwrap())
+    }
+
+    fn id(tokenizer: &Tokenizer, token: &str) -> u32 {
+        tokenizer.token_to_id(token).unwrap()
+    }
+
+    fn stream(decoder: &mut StreamDecoder, ids: &[u32]) -> Vec<Option<String>> {
+        ids.iter().map(|&id| decoder.step(id).unwrap()).collect()
+    }
+
+    #[test]
+    fn keeps_sentencepiece_leading_spaces() {
+        let tokenizer = tokenizer();
+        let ids = [id(&tokenizer, "▁Hello"), id(&tokenizer, "▁world")];
+        let mut decoder = StreamDecoder::new(tokenizer.clone(), true);
+        assert_eq!(
+            stream(&mut decoder, &ids),
+            [Some("Hello".to_string()), Some(" world".to_string())]
+        );
+        assert_eq!(tokenizer.decode(&ids[1..], true).unwrap(), "world");
+    }
+
+    #[test]
+    fn waits_for_complete_multibyte_characters() {
+        let tokenizer = tokenizer();
+        let bytes = |s: &str| -> Vec<u32> {
+            s.bytes()
+                .map(|b| id(&tokenizer, &format!("<0x{b:02X}>")))
+                .collect()
+        };
+        let mut ids = vec![id(&tokenizer, "▁Hello")];
+        ids.extend(bytes("ż😀"));
+        let mut decoder = StreamDecoder::new(tokenizer.clone(), true);
+        let deltas = stream(&mut decoder, &ids);
+        assert_eq!(
+            deltas,
+            [
+                Some("Hello".to_string()),
+                None,
+                Some("ż".to_string()),
+                None,
+                None,
+                None,
+                Some("😀".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_special_tokens_and_resets() {
+        let tokenizer = tokenizer();
+        let ids = [
+            id(&tokenizer, "<s>"),
+            id(&tokenizer, "▁Hello"),
+            id(&tokenizer, "<|im_end|>"),
+        ];
+        let mut decoder = StreamDecoder::new(tokenizer.clone(), true);
+        let text: String = stream(&mut decoder, &ids).into_iter().flatten().collect();
+        assert_eq!(text, "Hello");
+
+        decoder.reset();
+        assert_eq!(
+            decoder.step(id(&tokenizer, "▁world")).unwrap().as_deref(),
+            Some("world")
+        );
+    }
+}
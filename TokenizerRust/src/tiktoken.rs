@@ -0,0 +1,243 @@
+use crate::bpe;
+use crate::error::{Error, ErrorCode};
+use std::collections::HashMap;
+use std::path::Path;
+use tokenizers::decoders::byte_level::ByteLevel;
+use tokenizers::models::bpe::BPE;
+use tokenizers::pre_tokenizers::sequence::Sequence;
+use tokenizers::pre_tokenizers::split::{Split, SplitPattern};
+use tokenizers::{AddedToken, SplitDelimiterBehavior, Tokenizer};
+
+const CL100K_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+const O200K_PATTERN: &str = r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n/]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+/// A tiktoken encoding: the split regex and special tokens that its `.tiktoken` rank file lacks.
+pub struct Encoding {
+    pub name: &'static str,
+    pattern: &'static str,
+    special_tokens: &'static [(&'static str, u32)],
+}
+
+pub const ENCODINGS: [Encoding; 2] = [
+    Encoding {
+        name: "cl100k_base",
+        pattern: CL100K_PATTERN,
+        special_tokens: &[
+            ("<|endoftext|>", 100257),
+            ("<|fim_prefix|>", 100258),
+            ("<|fim_middle|>", 100259),
+            ("<|fim_suffix|>", 100260),
+            ("<|endofprompt|>", 100276),
+        ],
+    },
+    Encoding {
+        name: "o200k_base",
+        pattern: O200K_PATTERN,
+        special_tokens: &[("<|endoftext|>", 199999), ("<|endofprompt|>", 200018)],
+    },
+];
+
+// Checked in order, so longer prefixes come before the ones they extend.
+const MODEL_PREFIXES: [(&str, &str); 13] = [
+    ("gpt-4o", "o200k_base"),
+    ("chatgpt-4o", "o200k_base"),
+    ("gpt-4.1", "o200k_base"),
+    ("gpt-4.5", "o200k_base"),
+    ("gpt-5", "o200k_base"),
+    ("o1", "o200k_base"),
+    ("o3", "o200k_base"),
+    ("o4", "o200k_base"),
+    ("gpt-4", "cl100k_base"),
+    ("gpt-3.5", "cl100k_base"),
+    ("gpt-35", "cl100k_base"),
+    ("text-embedding-3", "cl100k_base"),
+    ("text-embedding-ada-002", "cl100k_base"),
+];
+
+pub fn encoding(name: &str) -> Result<&'static Encoding, Error> {
+    ENCODINGS.iter().find(|e| e.name == name).ok_or_else(|| {
+        Error::new(
+            ErrorCode::InvalidArgument,
+            format!("unknown tiktoken encoding {name}"),
+        )
+    })
+}
+
+/// Resolves the encoding an OpenAI model uses, e.g. `gpt-4o-mini` to `o200k_base`.
+pub fn encoding_for_model(model: &str) -> Result<&'static Encoding, Error> {
+    let model = model.trim().to_ascii_lowercase();
+    let model = model.strip_prefix("ft:").unwrap_or(&model);
+    MODEL_PREFIXES
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|&(_, name)| encoding(name))
+        .unwrap_or_else(|| {
+            Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("no tiktoken encoding is known for model {model}"),
+            ))
+        })
+}
+
+/// Builds a byte-level BPE tokenizer from a `.tiktoken` file of `base64(token) rank` lines.
+///
+/// tiktoken merges the adjacent pair whose concatenation has the lowest rank, which is
+/// a BPE whose merges are ordered by the rank of the merged token.
+pub fn load(path: impl AsRef<Path>, encoding: &Encoding) -> Result<Tokenizer, Error> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)
+        .map_err(|e| Error::io(e, format_args!("cannot read {}", path.display())))?;
+    let ranks = std::str::from_utf8(&bytes)
+        .map_err(|e| Error::new(ErrorCode::JsonParse, e.to_string()))
+        .and_then(parse_ranks)
+        .map_err(|e| {
+            Error::new(
+                ErrorCode::JsonParse,
+                format!("cannot parse {}: {}", path.display(), e.message),
+            )
+        })?;
+    build(&ranks, encoding)
+}
+
+fn parse_ranks(text: &str) -> Result<HashMap<Vec<u8>, u32>, Error> {
+    let invalid = |line: usize| Error::new(ErrorCode::JsonParse, format!("invalid line {line}"));
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let (token, rank) = line.split_once(' ').ok_or_else(|| invalid(i + 1))?;
+            let token = base64::decode(token).map_err(|_| invalid(i + 1))?;
+            let rank = rank.trim().parse().map_err(|_| invalid(i + 1))?;
+            Ok((token, rank))
+        })
+        .collect()
+}
+
+fn build(ranks: &HashMap<Vec<u8>, u32>, encoding: &Encoding) -> Result<Tokenizer, Error> {
+    let chars = bytes_to_unicode();
+    let to_str = |bytes: &[u8]| -> String { bytes.iter().map(|&b| chars[b as usize]).collect() };
+
+    let mut vocab: HashMap<String, u32> = ranks
+        .iter()
+        .map(|(token, &rank)| (to_str(token), rank))
+        .collect();
+    let merges = bpe::merges_by_rank(&vocab);
+    // Special ids leave gaps after the ranks, so they are placed in the model vocab
+    // rather than appended by the added vocabulary.
+    vocab.extend(
+        encoding
+            .special_tokens
+            .iter()
+            .map(|&(token, id)| (token.to_string(), id)),
+    );
+    let bpe = BPE::builder()
+        .vocab_and_merges(vocab, merges)
+        .build()
+        .map_err(|e| Error::new(ErrorCode::JsonParse, e.to_string()))?;
+
+    let split = Split::new(
+        SplitPattern::Regex(encoding.pattern.to_string()),
+        SplitDelimiterBehavior::Isolated,
+        false,
+    )?;
+    let byte_level = ByteLevel::new(false, false, false);
+    let mut tokenizer = Tokenizer::new(bpe);
+    tokenizer.with_pre_tokenizer(Sequence::new(vec![split.into(), byte_level.into()]));
+    tokenizer.with_decoder(byte_level);
+    let special: Vec<_> = encoding
+        .special_tokens
+        .iter()
+        .map(|&(token, _)| AddedToken::from(token, true))
+        .collect();
+    tokenizer.add_special_tokens(&special);
+    Ok(tokenizer)
+}
+
+/// GPT-2's reversible byte-to-character table, which byte-level BPE vocabularies are written in.
+fn bytes_to_unicode() -> [char; 256] {
+    let mut chars = ['\0'; 256];
+    let mut next = 256;
+    for b in 0..=255u8 {
+        let printable = matches!(b, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF);
+        chars[b as usize] = if printable {
+            b as char
+        } else {
+            next += 1;
+            char::from_u32(next - 1).unwrap()
+        };
+    }
+    chars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tiny.tiktoken");
+
+    fn tokenizer() -> Tokenizer {
+        load(FIXTURE, encoding("cl100k_base").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn merges_by_rank_like_tiktoken() {
+        let tokenizer = tokenizer();
+        let id = |token: &[u8]| -> u32 {
+            parse_ranks(&std::fs::read_to_string(FIXTURE).unwrap()).unwrap()[token]
+        };
+        let encoding = tokenizer.encode("hello world", false).unwrap();
+        assert_eq!(encoding.get_ids(), [id(b"hello"), id(b" world")]);
+        let encoding = tokenizer.encode("help", false).unwrap();
+        assert_eq!(encoding.get_ids(), [id(b"he"), id(b"l"), id(b"p")]);
+    }
+
+    #[test]
+    fn matches_known_cl100k_ids() {
+        // Byte and word ranks are cl100k_base's; the pieces in between only fix the merge order.
+        let subset = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/cl100k_subset.tiktoken"
+        );
+        let tokenizer = load(subset, encoding("cl100k_base").unwrap()).unwrap();
+        let ids = |text| tokenizer.encode(text, false).unwrap().get_ids().to_vec();
+        assert_eq!(ids("Hello, world!"), [9906, 11, 1917, 0]);
+        assert_eq!(ids("hello world"), [15339, 1917]);
+        assert_eq!(ids("hell\n"), [383, 657, 198]);
+    }
+
+    #[test]
+    fn round_trips_bytes_and_special_tokens() {
+        let tokenizer = tokenizer();
+        let text = "Zażółć gęślą jaźń 😀<|endoftext|>";
+        let ids = tokenizer.encode(text, false).unwrap().get_ids().to_vec();
+        assert_eq!(ids.last(), Some(&100257));
+        assert_eq!(tokenizer.decode(&ids, false).unwrap(), text);
+        assert_eq!(
+            tokenizer.decode(&ids, true).unwrap(),
+            "Zażółć gęślą jaźń 😀"
+        );
+    }
+
+    #[test]
+    fn resolves_encoding_from_model_name() {
+        let name = |model| encoding_for_model(model).map(|e| e.name);
+        assert_eq!(name("gpt-4o-mini").unwrap(), "o200k_base");
+        assert_eq!(name("GPT-4.1").unwrap(), "o200k_base");
+        assert_eq!(name("gpt-4-turbo").unwrap(), "cl100k_base");
+        assert_eq!(name("ft:gpt-3.5-turbo:org::id").unwrap(), "cl100k_base");
+        assert!(name("gemini-1.5-pro").is_err());
+    }
+
+    #[test]
+    fn classifies_load_failures() {
+        let cl100k = encoding("cl100k_base").unwrap();
+        let code = |path| load(path, cl100k).err().unwrap().code;
+        assert_eq!(
+            code("/definitely/missing.tiktoken"),
+            ErrorCode::FileNotFound
+        );
+        assert_eq!(code(env!("CARGO_MANIFEST_DIR")), ErrorCode::Io);
+        let onnx = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/encoder.onnx");
+        assert_eq!(code(onnx), ErrorCode::JsonParse);
+    }
+}